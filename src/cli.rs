@@ -0,0 +1,106 @@
+use super::*;
+
+use clap::{Parser, Subcommand};
+
+/// Runs the monitor when started without a command.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Export recorded probe history and uptime per site.
+    Export {
+        /// First day to include, YYYY-MM-DD.
+        #[arg(long)]
+        from: NaiveDate,
+        /// Last day to include, YYYY-MM-DD.
+        #[arg(long)]
+        to: NaiveDate,
+        #[arg(long, value_enum, default_value = "csv")]
+        format: ExportFormat,
+        /// File name without the extension.  Defaults to export-<from>-<to>.
+        #[arg(long)]
+        out: Option<String>,
+    },
+    /// Write an availability report with outages and recovery times.
+    Report {
+        /// First day to include, YYYY-MM-DD.
+        #[arg(long)]
+        from: NaiveDate,
+        /// Last day to include, YYYY-MM-DD.
+        #[arg(long)]
+        to: NaiveDate,
+        #[arg(long, value_enum, default_value = "monthly")]
+        period: Period,
+        #[arg(long, value_enum, default_value = "html")]
+        format: ReportFormat,
+        /// Report file.  Defaults to report-<from>-<to>.html/md.
+        #[arg(long)]
+        out: Option<String>,
+    },
+}
+
+/// The release build is a Windows GUI program, which gets no console, so anything printed would go
+/// nowhere.  Attaches to the console of the terminal it was started from, if there is one.
+#[cfg(windows)]
+pub fn attach_console() {
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+    // SAFETY: Takes no pointers.  Fails harmlessly when there's no parent console, e.g. started from Explorer.
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+/// Other platforms print to the terminal as they are.
+#[cfg(not(windows))]
+pub fn attach_console() {}
+
+/// Runs a command line command.  Returns the exit code.
+pub fn run_command(command: Command) -> i32 {
+    match command {
+        Command::Export {
+            from,
+            to,
+            format,
+            out,
+        } => {
+            let base = out.unwrap_or_else(|| export_base(from, to));
+            match export(from, to, format, &base) {
+                Ok(files) => {
+                    for file in files {
+                        println!("Wrote {}", file.display());
+                    }
+                    0
+                }
+                Err(e) => {
+                    eprintln!("Export failed: {e}");
+                    1
+                }
+            }
+        }
+        Command::Report {
+            from,
+            to,
+            period,
+            format,
+            out,
+        } => match write_report(from, to, period, format, out) {
+            Ok(file) => {
+                println!("Wrote {}", file.display());
+                0
+            }
+            Err(e) => {
+                eprintln!("Report failed: {e}");
+                1
+            }
+        },
+    }
+}
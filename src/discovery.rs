@@ -0,0 +1,232 @@
+use super::*;
+
+use futures::StreamExt;
+use std::net::Ipv4Addr;
+
+/// Biggest range a scan will sweep.  A /20 is 4094 hosts.
+pub const MIN_SCAN_PREFIX: u32 = 20;
+/// Most of a range won't answer, so scan pings don't wait as long as site pings.
+pub const SCAN_TIMEOUT: u64 = 1;
+/// Scan pings out at once.  Kept apart from the scheduler's limit so a scan can't hold up site pings.
+pub const SCAN_CONCURRENCY: usize = 32;
+
+/// A host that answered a discovery scan.
+#[derive(Lens, Clone, PartialEq, Data)]
+pub struct FoundHost {
+    pub addr: String,
+    pub hostname: String, // Reverse DNS name, empty if it doesn't have one.
+    pub name: String,     // What it'll be called in sites.json.
+    pub response: Option<Duration>,
+    pub selected: bool,
+}
+
+/// Discovery scan results for the discovery panel.  Hidden until a scan is started.
+#[derive(Lens, Clone, PartialEq, Data, Default)]
+pub struct Discovery {
+    pub range: String,
+    pub hosts: Vec<FoundHost>,
+    pub running: bool,
+    pub shown: bool,
+    pub status: String,
+}
+impl Discovery {
+    /// Name for a found host: its reverse DNS name up to the first dot, or its address if it has none.
+    /// Gets a number on the end if a site or another found host already has it.
+    pub fn site_name(
+        &self,
+        hostname: &str,
+        addr: &str,
+        config: &BTreeMap<String, SiteConfig>,
+    ) -> String {
+        let base = match hostname.split('.').next() {
+            Some(short) if !short.is_empty() => short.to_string(),
+            _ => addr.to_string(),
+        };
+        let taken =
+            |name: &str| config.contains_key(name) || self.hosts.iter().any(|h| h.name == name);
+        let mut name = base.clone();
+        let mut n = 2;
+        while taken(&name) {
+            name = format!("{base}-{n}");
+            n += 1;
+        }
+        name
+    }
+}
+
+/// Every host address in an IPv4 CIDR range like "10.1.0.0/24".  Leaves out the network & broadcast addresses.
+pub fn cidr_hosts(range: &str) -> Result<Vec<Ipv4Addr>, String> {
+    let range = range.trim();
+    let (addr, prefix) = range.split_once('/').unwrap_or((range, "32"));
+    let addr: Ipv4Addr = addr
+        .parse()
+        .map_err(|_| format!("{addr} isn't an IPv4 address"))?;
+    let prefix: u32 = prefix
+        .parse()
+        .ok()
+        .filter(|p| *p <= 32)
+        .ok_or_else(|| format!("/{prefix} isn't a prefix length (0-32)"))?;
+    if prefix < MIN_SCAN_PREFIX {
+        return Err(format!(
+            "/{prefix} is too big to scan, use /{MIN_SCAN_PREFIX} or smaller"
+        ));
+    }
+
+    let size = 1u32 << (32 - prefix);
+    let network = u32::from(addr) & !(size - 1);
+    let hosts = (network..=network + (size - 1)).map(Ipv4Addr::from);
+    // /31 & /32 have no network or broadcast address to skip.
+    if prefix >= 31 {
+        Ok(hosts.collect())
+    } else {
+        Ok(hosts.skip(1).take(size as usize - 2).collect())
+    }
+}
+
+/// Reverse DNS name for an address, empty if it doesn't have one.
+async fn reverse_dns(addr: IpAddr) -> String {
+    // The system resolver blocks, keep it off the runtime threads.
+    let name = tokio::task::spawn_blocking(move || dns_lookup::lookup_addr(&addr))
+        .await
+        .ok()
+        .and_then(|r| r.ok())
+        .unwrap_or_default();
+    // Addresses without a name come back as the address itself.
+    if name == addr.to_string() {
+        String::new()
+    } else {
+        name
+    }
+}
+
+/// Pings every host in a range with the IPv4 client the sites use.  Sends each host that answers
+/// back to the GUI thread as it's found, with its reverse DNS name.
+pub async fn discover(cx: ContextProxy, client: Result<Client, ClientError>, range: String) {
+    let finish = |mut cx: ContextProxy, status: String| {
+        if cx.emit(ViziaEvent::DiscoveryFinished(status)).is_err() {
+            error!("Couldn't send the discovery scan result to the GUI");
+        }
+    };
+    let hosts = match cidr_hosts(&range) {
+        Ok(hosts) => hosts,
+        Err(e) => return finish(cx, e),
+    };
+    let client = match client {
+        Ok(client) => client,
+        Err(e) => return finish(cx, e.message),
+    };
+    let total = hosts.len();
+    info!(range = %range, hosts = total, "Discovery scan started");
+
+    let found = futures::stream::iter(hosts)
+        .map(|addr| {
+            let client = client.clone();
+            let mut cx = cx.clone();
+            async move {
+                let site = SiteAddress {
+                    name: addr.to_string(),
+                    addr: IpAddr::V4(addr),
+                };
+                let response = ping(
+                    client,
+                    site,
+                    0,
+                    SCAN_TIMEOUT,
+                    Payload::Tiny.to_bytes(),
+                    false,
+                )
+                .await;
+                if response.is_err() {
+                    return false;
+                }
+                let host = FoundHost {
+                    addr: addr.to_string(),
+                    hostname: reverse_dns(IpAddr::V4(addr)).await,
+                    name: String::new(), // Named by the GUI, it knows what's taken.
+                    response: response.response,
+                    selected: false,
+                };
+                let _ = cx.emit(ViziaEvent::DiscoveryHost(host));
+                true
+            }
+        })
+        .buffer_unordered(SCAN_CONCURRENCY)
+        .filter(|up| futures::future::ready(*up))
+        .count()
+        .await;
+
+    info!(range = %range, found, "Discovery scan finished");
+    finish(
+        cx,
+        format!("{found} of {total} addresses in {range} answered"),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs(hosts: &[Ipv4Addr]) -> Vec<String> {
+        hosts.iter().map(|h| h.to_string()).collect()
+    }
+
+    #[test]
+    fn single_address() {
+        assert_eq!(addrs(&cidr_hosts("10.1.2.3/32").unwrap()), ["10.1.2.3"]);
+        // No prefix means just the one address.
+        assert_eq!(addrs(&cidr_hosts(" 10.1.2.3 ").unwrap()), ["10.1.2.3"]);
+    }
+
+    #[test]
+    fn point_to_point_keeps_both() {
+        assert_eq!(
+            addrs(&cidr_hosts("10.1.2.4/31").unwrap()),
+            ["10.1.2.4", "10.1.2.5"]
+        );
+    }
+
+    #[test]
+    fn skips_network_and_broadcast() {
+        assert_eq!(
+            addrs(&cidr_hosts("192.168.1.0/30").unwrap()),
+            ["192.168.1.1", "192.168.1.2"]
+        );
+        let hosts = cidr_hosts("10.1.0.0/24").unwrap();
+        assert_eq!(hosts.len(), 254);
+        assert_eq!(hosts[0], Ipv4Addr::new(10, 1, 0, 1));
+        assert_eq!(hosts[253], Ipv4Addr::new(10, 1, 0, 254));
+    }
+
+    #[test]
+    fn biggest_range() {
+        let hosts = cidr_hosts("10.16.0.0/20").unwrap();
+        assert_eq!(hosts.len(), 4094);
+        assert_eq!(hosts[0], Ipv4Addr::new(10, 16, 0, 1));
+        assert_eq!(hosts[4093], Ipv4Addr::new(10, 16, 15, 254));
+        assert!(cidr_hosts("10.16.0.0/19").is_err());
+    }
+
+    #[test]
+    fn misaligned_address_uses_its_network() {
+        assert_eq!(
+            cidr_hosts("10.1.0.77/24").unwrap(),
+            cidr_hosts("10.1.0.0/24").unwrap()
+        );
+        assert_eq!(
+            addrs(&cidr_hosts("10.1.0.7/30").unwrap()),
+            ["10.1.0.5", "10.1.0.6"]
+        );
+        assert_eq!(
+            addrs(&cidr_hosts("10.1.0.5/31").unwrap()),
+            ["10.1.0.4", "10.1.0.5"]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_ranges() {
+        assert!(cidr_hosts("10.1.0.0/33").is_err());
+        assert!(cidr_hosts("10.1.0.0/x").is_err());
+        assert!(cidr_hosts("10.1.0/24").is_err());
+        assert!(cidr_hosts("fd00::/120").is_err());
+    }
+}
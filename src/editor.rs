@@ -0,0 +1,351 @@
+use super::*;
+
+use serde_json::{Map, Value};
+
+/// A site as listed in the site editor.
+#[derive(Lens, Clone, PartialEq, Data)]
+pub struct ListedSite {
+    pub name: String,
+    pub disabled: bool,
+}
+
+/// Text fields in the site editor form.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EditorField {
+    Name,
+    Address,
+    AddressV6,
+    Group,
+    Parent,
+    Interval,
+}
+
+/// The site editor form.  Fields are kept as typed, they're only checked on save.
+#[derive(Lens, Clone, PartialEq, Data, Default)]
+pub struct SiteEditor {
+    pub sites: Vec<ListedSite>,
+    pub original: String, // Name of the site being edited, empty for a new one.
+    pub name: String,
+    pub address: String,
+    pub address_v6: String,
+    pub group: String,
+    pub parent: String,
+    pub interval: String,
+    pub mtr: bool,
+    pub disabled: bool,
+    pub confirm_delete: bool, // Delete was pressed once, the next press removes the site.
+    pub status: String,
+}
+impl SiteEditor {
+    pub fn new() -> Self {
+        let mut editor = SiteEditor::default();
+        editor.reload();
+        editor
+    }
+
+    /// Refreshes the site list from sites.json.
+    pub fn reload(&mut self) {
+        match list_sites() {
+            Ok(sites) => self.sites = sites,
+            Err(e) => self.status = format!("Couldn't read sites.json: {e}"),
+        }
+    }
+
+    /// Empties the form for a new site.
+    pub fn clear(&mut self) {
+        *self = SiteEditor {
+            sites: std::mem::take(&mut self.sites),
+            ..Default::default()
+        };
+    }
+
+    /// Fills the form from a site's sites.json entry.
+    pub fn pick(&mut self, name: &str, entry: &Value) {
+        let text = |key: &str| match entry.get(key) {
+            Some(Value::String(s)) => s.clone(),
+            Some(Value::Null) | None => String::new(),
+            Some(other) => other.to_string(),
+        };
+        let flag = |key: &str| entry.get(key).and_then(Value::as_bool).unwrap_or(false);
+        *self = SiteEditor {
+            sites: std::mem::take(&mut self.sites),
+            original: name.to_string(),
+            name: name.to_string(),
+            // Bare address entries are just a string.
+            address: entry
+                .as_str()
+                .map(String::from)
+                .unwrap_or_else(|| text("address")),
+            address_v6: text("address_v6"),
+            group: text("group"),
+            parent: text("parent"),
+            interval: text("interval"),
+            mtr: flag("mtr"),
+            disabled: flag("disabled"),
+            confirm_delete: false,
+            status: String::new(),
+        };
+    }
+
+    pub fn set(&mut self, field: EditorField, text: String) {
+        let value = match field {
+            EditorField::Name => &mut self.name,
+            EditorField::Address => &mut self.address,
+            EditorField::AddressV6 => &mut self.address_v6,
+            EditorField::Group => &mut self.group,
+            EditorField::Parent => &mut self.parent,
+            EditorField::Interval => &mut self.interval,
+        };
+        *value = text;
+        self.confirm_delete = false;
+    }
+
+    /// Writes the form to sites.json.  The site's other fields are left as they were.
+    pub fn save(&mut self) -> Result<String, String> {
+        let name = self.name.trim().to_string();
+        if name.is_empty() {
+            return Err("The site needs a name".to_string());
+        }
+        let existing = if self.original.is_empty() {
+            None
+        } else {
+            Some(read_site_entry(&self.original)?)
+        };
+        let entry = self.to_entry(&name, existing)?;
+        replace_site(&self.original, &name, entry)?;
+        info!(site = %name, was = %self.original, "Saved site from the editor");
+        let status = if self.original.is_empty() {
+            format!("Added {name}")
+        } else {
+            format!("Saved {name}")
+        };
+        self.original = name.clone();
+        self.name = name;
+        self.confirm_delete = false;
+        Ok(status)
+    }
+
+    /// Removes the site being edited.  Needs pressing twice, the first press only asks.
+    pub fn delete(&mut self) -> Result<Option<String>, String> {
+        if self.original.is_empty() {
+            return Err("Pick a site to delete".to_string());
+        }
+        if !self.confirm_delete {
+            self.confirm_delete = true;
+            return Ok(None);
+        }
+        delete_site(&self.original)?;
+        info!(site = %self.original, "Deleted site from the editor");
+        let status = format!("Deleted {}", self.original);
+        self.clear();
+        Ok(Some(status))
+    }
+
+    /// Builds the sites.json entry, on top of the site's existing one so fields the editor doesn't know about are kept.
+    fn to_entry(&self, name: &str, existing: Option<Value>) -> Result<Value, String> {
+        let address = self.address.trim();
+        let address: IpAddr = address
+            .parse()
+            .map_err(|_| format!("Address \"{address}\" isn't an IP address"))?;
+        let address_v6 = match self.address_v6.trim() {
+            "" => None,
+            v6 => Some(
+                v6.parse::<IpAddr>()
+                    .ok()
+                    .filter(|a| a.is_ipv6())
+                    .ok_or_else(|| format!("IPv6 address \"{v6}\" isn't an IPv6 address"))?,
+            ),
+        };
+        let interval = match self.interval.trim() {
+            "" => None,
+            i => Some(
+                i.parse::<u64>()
+                    .ok()
+                    .filter(|i| *i > 0)
+                    .ok_or_else(|| format!("Interval \"{i}\" isn't a whole number of seconds"))?,
+            ),
+        };
+        let parent = self.parent.trim();
+        if !parent.is_empty() {
+            if parent == name || parent == self.original {
+                return Err("A site can't be its own parent".to_string());
+            }
+            match self.sites.iter().find(|s| s.name == parent) {
+                None => return Err(format!("No site named \"{parent}\" to be the parent")),
+                // Disabled sites aren't monitored, so they can't tell us anything as a parent.
+                Some(site) if site.disabled => {
+                    return Err(format!("\"{parent}\" is disabled, it can't be a parent"))
+                }
+                Some(_) => {}
+            }
+        }
+        let group = self.group.trim();
+
+        let short_form = !matches!(existing, Some(Value::Object(_)));
+        let mut fields = match existing {
+            Some(Value::Object(fields)) => fields,
+            _ => Map::new(),
+        };
+        set_field(&mut fields, "address", Some(address.to_string().into()));
+        set_field(
+            &mut fields,
+            "address_v6",
+            address_v6.map(|a| a.to_string().into()),
+        );
+        set_field(
+            &mut fields,
+            "group",
+            (!group.is_empty()).then(|| group.into()),
+        );
+        set_field(
+            &mut fields,
+            "parent",
+            (!parent.is_empty()).then(|| parent.into()),
+        );
+        set_field(&mut fields, "interval", interval.map(Value::from));
+        set_field(&mut fields, "mtr", self.mtr.then_some(Value::Bool(true)));
+        set_field(
+            &mut fields,
+            "disabled",
+            self.disabled.then_some(Value::Bool(true)),
+        );
+
+        // Sites that were just an address stay that way if nothing else was set.
+        if short_form && fields.len() == 1 {
+            Ok(fields.shift_remove("address").unwrap_or(Value::Null))
+        } else {
+            Ok(Value::Object(fields))
+        }
+    }
+}
+
+/// Sets a field, or removes it if it's empty.  Existing fields keep their place.
+fn set_field(fields: &mut Map<String, Value>, key: &str, value: Option<Value>) {
+    match value {
+        Some(value) => {
+            fields.insert(key.to_string(), value);
+        }
+        None => {
+            fields.shift_remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn picked(name: &str, entry: &Value) -> SiteEditor {
+        let mut editor = SiteEditor {
+            sites: ["router", "old-router", name]
+                .iter()
+                .map(|n| ListedSite {
+                    name: n.to_string(),
+                    disabled: *n == "old-router",
+                })
+                .collect(),
+            ..Default::default()
+        };
+        editor.pick(name, entry);
+        editor
+    }
+
+    #[test]
+    fn unchanged_entry_round_trips() {
+        let entry = json!({
+            "address": "10.0.0.1",
+            "ttl": 5,
+            "group": "Lincoln Elem",
+            "interval": 60,
+            "note": {"closet": "B"},
+            "mtr": true
+        });
+        let editor = picked("lincoln", &entry);
+        let saved = editor.to_entry("lincoln", Some(entry.clone())).unwrap();
+        assert_eq!(saved, entry);
+        assert!(saved
+            .as_object()
+            .unwrap()
+            .keys()
+            .eq(entry.as_object().unwrap().keys()));
+    }
+
+    #[test]
+    fn keeps_unknown_fields_and_their_place() {
+        let entry = json!({"address": "10.0.0.1", "ttl": 5, "group": "A", "note": "x"});
+        let mut editor = picked("lincoln", &entry);
+        editor.set(EditorField::Group, "B".to_string());
+        editor.set(EditorField::Parent, "router".to_string());
+        let saved = editor.to_entry("lincoln", Some(entry)).unwrap();
+        let keys: Vec<&String> = saved.as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["address", "ttl", "group", "note", "parent"]);
+        assert_eq!(saved["ttl"], 5);
+        assert_eq!(saved["group"], "B");
+        assert_eq!(saved["note"], "x");
+    }
+
+    #[test]
+    fn cleared_fields_are_removed() {
+        let entry = json!({"address": "10.0.0.1", "group": "A", "interval": 30, "mtr": true});
+        let mut editor = picked("lincoln", &entry);
+        editor.set(EditorField::Group, " ".to_string());
+        editor.set(EditorField::Interval, String::new());
+        editor.mtr = false;
+        let saved = editor.to_entry("lincoln", Some(entry)).unwrap();
+        assert_eq!(saved, json!({"address": "10.0.0.1"}));
+    }
+
+    #[test]
+    fn bare_address_stays_bare() {
+        let entry = json!("10.0.0.1");
+        let mut editor = picked("lincoln", &entry);
+        assert_eq!(editor.address, "10.0.0.1");
+        assert_eq!(
+            editor.to_entry("lincoln", Some(entry.clone())).unwrap(),
+            entry
+        );
+        editor.set(EditorField::Address, "10.0.0.2".to_string());
+        assert_eq!(
+            editor.to_entry("lincoln", Some(entry.clone())).unwrap(),
+            json!("10.0.0.2")
+        );
+        // Anything else set turns it into an object.
+        editor.set(EditorField::Group, "A".to_string());
+        assert_eq!(
+            editor.to_entry("lincoln", Some(entry)).unwrap(),
+            json!({"address": "10.0.0.2", "group": "A"})
+        );
+    }
+
+    #[test]
+    fn new_site_with_only_an_address_is_bare() {
+        let mut editor = SiteEditor::default();
+        editor.set(EditorField::Address, "10.0.0.9".to_string());
+        assert_eq!(editor.to_entry("new", None).unwrap(), json!("10.0.0.9"));
+        editor.mtr = true;
+        assert_eq!(
+            editor.to_entry("new", None).unwrap(),
+            json!({"address": "10.0.0.9", "mtr": true})
+        );
+    }
+
+    #[test]
+    fn rejects_bad_fields() {
+        let entry = json!({"address": "10.0.0.1"});
+        let check = |field: EditorField, text: &str| {
+            let mut editor = picked("lincoln", &entry);
+            editor.set(field, text.to_string());
+            editor.to_entry("lincoln", Some(entry.clone()))
+        };
+        assert!(check(EditorField::Address, "10.0.0").is_err());
+        assert!(check(EditorField::AddressV6, "10.0.0.2").is_err());
+        assert!(check(EditorField::AddressV6, "fd00::1").is_ok());
+        assert!(check(EditorField::Interval, "0").is_err());
+        assert!(check(EditorField::Interval, "1.5").is_err());
+        assert!(check(EditorField::Parent, "lincoln").is_err());
+        assert!(check(EditorField::Parent, "nowhere").is_err());
+        assert!(check(EditorField::Parent, "old-router").is_err());
+        assert!(check(EditorField::Parent, "router").is_ok());
+    }
+}
@@ -0,0 +1,164 @@
+use super::*;
+
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+/// Uptime for one site over the exported range.
+#[derive(Serialize, Clone)]
+pub struct SiteUptime {
+    pub site: String,
+    pub probes: u64,
+    pub failed: u64,
+    pub uptime_percent: f64, // Share of the time covered by probes, not of the probes themselves.
+    pub avg_rtt_ms: Option<f64>,
+}
+
+/// Works out uptime per site, in name order.  Records must be oldest first.
+pub fn uptime(records: &[ProbeRecord]) -> Vec<SiteUptime> {
+    #[derive(Default)]
+    struct Tally {
+        probes: u64,
+        failed: u64,
+        rtt_sum: f64,
+        up_secs: f64,
+        total_secs: f64,
+    }
+    let mut sites: BTreeMap<&str, Tally> = BTreeMap::new();
+    for (record, span) in records.iter().zip(record_spans(records)) {
+        let tally = sites.entry(record.site.as_str()).or_default();
+        tally.probes += 1;
+        tally.total_secs += span;
+        match record.rtt_ms {
+            Some(rtt) => {
+                tally.rtt_sum += rtt;
+                tally.up_secs += span;
+            }
+            None => tally.failed += 1,
+        }
+    }
+    sites
+        .into_iter()
+        .map(|(site, tally)| {
+            let answered = tally.probes - tally.failed;
+            SiteUptime {
+                site: site.to_string(),
+                probes: tally.probes,
+                failed: tally.failed,
+                uptime_percent: if tally.total_secs > 0.0 {
+                    tally.up_secs / tally.total_secs * 100.0
+                } else {
+                    answered as f64 / tally.probes as f64 * 100.0
+                },
+                avg_rtt_ms: (answered > 0).then(|| tally.rtt_sum / answered as f64),
+            }
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+struct JsonExport<'a> {
+    from: NaiveDate,
+    to: NaiveDate,
+    uptime: Vec<SiteUptime>,
+    probes: &'a [ProbeRecord],
+}
+
+/// Exports history & uptime for `from` through `to`.  CSV goes to `<base>-uptime.csv` & `<base>-probes.csv`,
+/// JSON to `<base>.json`.  Returns the files written.
+pub fn export(
+    from: NaiveDate,
+    to: NaiveDate,
+    format: ExportFormat,
+    base: &str,
+) -> Result<Vec<PathBuf>, String> {
+    if to < from {
+        return Err("End date is before start date".to_string());
+    }
+    let records = load_records(from, to);
+    let uptime = uptime(&records);
+    match format {
+        ExportFormat::Csv => {
+            let uptime_file = PathBuf::from(format!("{base}-uptime.csv"));
+            write_csv(&uptime_file, &uptime)?;
+            let probes_file = PathBuf::from(format!("{base}-probes.csv"));
+            write_csv(&probes_file, &records)?;
+            Ok(vec![uptime_file, probes_file])
+        }
+        ExportFormat::Json => {
+            let file = PathBuf::from(format!("{base}.json"));
+            let data = serde_json::to_string_pretty(&JsonExport {
+                from,
+                to,
+                uptime,
+                probes: &records,
+            })
+            .map_err(|e| e.to_string())?;
+            fs::write(&file, data).map_err(|e| e.to_string())?;
+            Ok(vec![file])
+        }
+    }
+}
+
+fn write_csv<T: Serialize>(path: &Path, rows: &[T]) -> Result<(), String> {
+    let mut writer = csv::Writer::from_path(path).map_err(|e| e.to_string())?;
+    for row in rows {
+        writer.serialize(row).map_err(|e| e.to_string())?;
+    }
+    writer.flush().map_err(|e| e.to_string())
+}
+
+/// Default file name for an export, e.g. "export-2024-04-01-2024-04-30".
+pub fn export_base(from: NaiveDate, to: NaiveDate) -> String {
+    format!("export-{from}-{to}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeDelta, TimeZone};
+
+    fn record(site: &str, secs: i64, rtt_ms: Option<f64>) -> ProbeRecord {
+        ProbeRecord {
+            time: Local.with_ymd_and_hms(2024, 5, 6, 10, 0, 0).unwrap() + TimeDelta::seconds(secs),
+            site: site.to_string(),
+            rtt_ms,
+        }
+    }
+
+    #[test]
+    fn uptime_is_weighted_by_time() {
+        // Up for a minute, then down with quicker probes for 20 seconds.
+        let records = [
+            record("a", 0, Some(10.0)),
+            record("a", 60, None),
+            record("a", 70, None),
+            record("a", 80, Some(20.0)),
+        ];
+        let uptime = uptime(&records);
+        assert_eq!(uptime.len(), 1);
+        let a = &uptime[0];
+        assert_eq!((a.probes, a.failed), (4, 2));
+        assert_eq!(a.uptime_percent, 70.0 / 90.0 * 100.0);
+        assert_eq!(a.avg_rtt_ms, Some(15.0));
+    }
+
+    #[test]
+    fn uptime_falls_back_to_counting_probes() {
+        // No time between the records to weight them by.
+        let records = [
+            record("a", 0, Some(10.0)),
+            record("a", 0, None),
+            record("b", 0, None),
+        ];
+        let uptime = uptime(&records);
+        assert_eq!(uptime[0].uptime_percent, 50.0);
+        assert_eq!(uptime[1].site, "b");
+        assert_eq!(uptime[1].uptime_percent, 0.0);
+        assert_eq!(uptime[1].avg_rtt_ms, None);
+    }
+}
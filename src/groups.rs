@@ -0,0 +1,88 @@
+use super::*;
+
+/// A set of sites sharing a "group" in sites.json, shown as a collapsible section in the site list.
+#[derive(Lens, Clone, PartialEq, Data)]
+pub struct SiteGroup {
+    pub name: String, // Empty for sites that aren't in a group.
+    pub sites: Vec<PingResponse>,
+    pub history: Vec<SiteAverage>,
+    pub collapsed: bool,
+}
+impl SiteGroup {
+    pub fn new(name: String, collapsed: bool) -> Self {
+        SiteGroup {
+            name,
+            sites: Vec::new(),
+            history: Vec::new(),
+            collapsed,
+        }
+    }
+
+    /// Sites that are down in their own right.  Sites behind a failed parent, with no recent result, or with none yet don't count.
+    pub fn down(&self) -> usize {
+        self.sites
+            .iter()
+            .filter(|s| s.is_down() && !s.parent_down && !s.stale)
+            .count()
+    }
+
+    pub fn unreachable(&self) -> usize {
+        self.sites
+            .iter()
+            .filter(|s| s.parent_down && !s.stale)
+            .count()
+    }
+
+    pub fn stale(&self) -> usize {
+        self.sites.iter().filter(|s| s.stale).count()
+    }
+
+    /// Group header text, "all up" or how many sites are down.
+    pub fn summary(&self) -> String {
+        let counts = [
+            (self.down(), "down"),
+            (self.unreachable(), "unreachable"),
+            (self.stale(), "stale"),
+        ];
+        let parts: Vec<String> = counts
+            .iter()
+            .filter(|(n, _)| *n > 0)
+            .map(|(n, what)| format!("{n} {what}"))
+            .collect();
+        if parts.is_empty() {
+            "all up".to_string()
+        } else {
+            parts.join(", ")
+        }
+    }
+}
+
+/// Sorts sites & their averages into groups.  Groups are in name order, ungrouped sites go last.
+pub fn build_groups(
+    sites: &[PingResponse],
+    history: &[SiteAverage],
+    config: &BTreeMap<String, SiteConfig>,
+    collapsed: &[String],
+) -> Vec<SiteGroup> {
+    let mut groups: BTreeMap<String, SiteGroup> = BTreeMap::new();
+    let mut ungrouped = SiteGroup::new(String::new(), false);
+
+    for site in sites {
+        let group = match config.get(&site.name).and_then(|c| c.group.as_ref()) {
+            Some(name) => groups
+                .entry(name.clone())
+                .or_insert_with(|| SiteGroup::new(name.clone(), collapsed.contains(name))),
+            None => &mut ungrouped,
+        };
+        group.sites.push(site.clone());
+        if let Some(avg) = history.iter().find(|h| h.name == site.name) {
+            group.history.push(avg.clone());
+        }
+    }
+
+    let mut groups: Vec<SiteGroup> = groups.into_values().collect();
+    if !ungrouped.sites.is_empty() {
+        groups.push(ungrouped);
+    }
+    groups
+}
@@ -0,0 +1,185 @@
+use super::*;
+
+use axum::{
+    extract::{Path as UrlPath, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse},
+    routing::{get, post, put},
+    Json, Router,
+};
+use std::net::SocketAddr;
+use subtle::ConstantTimeEq;
+
+/// Everything the web server's handlers need.
+#[derive(Clone)]
+pub struct HttpState {
+    pub metrics: MetricsStore,
+    pub status: StatusStore,
+    pub tx: mpsc::Sender<TokioEvent>,
+    pub api_token: Option<String>, // Control endpoints are off without one.
+}
+
+type ApiResult = Result<StatusCode, (StatusCode, String)>;
+
+/// Body for PUT /api/settings.  Anything left out stays as it is.
+#[derive(Deserialize)]
+struct SettingsChange {
+    timeout: Option<u64>,
+    payload: Option<usize>, // Bytes, one of the sizes in the payload menu.
+}
+
+/// Read-only status page.  Polls the JSON API to stay current.
+const DASHBOARD: &str = include_str!("../dashboard.html");
+
+/// Runs the built-in web server.  Gives up if the address can't be bound.
+pub async fn serve(addr: SocketAddr, state: HttpState) {
+    let app = Router::new()
+        .route("/", get(dashboard))
+        .route("/api/sites", get(sites))
+        .route("/api/sites/:name/history", get(history))
+        .route("/api/sites/:name", put(put_site).delete(remove_site))
+        .route("/api/refresh", post(refresh))
+        .route("/api/reload", post(reload))
+        .route("/api/settings", put(change_settings))
+        .route("/metrics", get(metrics))
+        .with_state(state);
+    let listener = match tokio::net::TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(%addr, error = %e, "Couldn't start the web server");
+            return;
+        }
+    };
+    info!(%addr, "Web server listening");
+    if let Err(e) = axum::serve(listener, app).await {
+        error!(error = %e, "Web server stopped");
+    }
+}
+
+async fn metrics(State(state): State<HttpState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
+}
+
+async fn dashboard() -> Html<&'static str> {
+    Html(DASHBOARD)
+}
+
+async fn sites(State(state): State<HttpState>) -> Json<Vec<ApiSite>> {
+    Json(state.status.sites())
+}
+
+async fn history(
+    State(state): State<HttpState>,
+    UrlPath(name): UrlPath<String>,
+) -> Result<Json<Vec<HistoryPoint>>, StatusCode> {
+    state
+        .status
+        .history(&name)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Checks for "Authorization: Bearer <api_token>".
+fn authorize(state: &HttpState, headers: &HeaderMap) -> Result<(), (StatusCode, String)> {
+    let Some(token) = &state.api_token else {
+        return Err((StatusCode::NOT_FOUND, "Control API is disabled".to_string()));
+    };
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+    match given {
+        // Constant time, so the token can't be guessed a byte at a time from response times.
+        Some(given) if bool::from(given.as_bytes().ct_eq(token.as_bytes())) => Ok(()),
+        _ => {
+            warn!("Control API request with a bad or missing token");
+            Err((StatusCode::UNAUTHORIZED, "Bad or missing token".to_string()))
+        }
+    }
+}
+
+/// Hands a command to the tokio thread, same channel the GUI uses.
+fn send(state: &HttpState, command: ApiCommand) -> ApiResult {
+    state
+        .tx
+        .send(TokioEvent::Api(command))
+        .map(|_| StatusCode::ACCEPTED)
+        .map_err(|_| {
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Monitor is shutting down".to_string(),
+            )
+        })
+}
+
+async fn refresh(State(state): State<HttpState>, headers: HeaderMap) -> ApiResult {
+    authorize(&state, &headers)?;
+    send(&state, ApiCommand::Refresh)
+}
+
+async fn reload(State(state): State<HttpState>, headers: HeaderMap) -> ApiResult {
+    authorize(&state, &headers)?;
+    // Say what's wrong with the file here, the reload itself would just keep the current sites.
+    edit_sites(|| load_sites().map(drop)).await?;
+    send(&state, ApiCommand::ReloadSites)
+}
+
+/// Runs a sites.json read or edit off the runtime threads, it's blocking file IO.
+async fn edit_sites(
+    edit: impl FnOnce() -> Result<(), String> + Send + 'static,
+) -> Result<(), (StatusCode, String)> {
+    tokio::task::spawn_blocking(edit)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))
+}
+
+async fn put_site(
+    State(state): State<HttpState>,
+    headers: HeaderMap,
+    UrlPath(name): UrlPath<String>,
+    Json(entry): Json<serde_json::Value>,
+) -> ApiResult {
+    authorize(&state, &headers)?;
+    let site = name.clone();
+    edit_sites(move || write_site(&site, entry)).await?;
+    info!(site = %name, "Site saved through the control API");
+    send(&state, ApiCommand::ReloadSites)
+}
+
+async fn remove_site(
+    State(state): State<HttpState>,
+    headers: HeaderMap,
+    UrlPath(name): UrlPath<String>,
+) -> ApiResult {
+    authorize(&state, &headers)?;
+    let site = name.clone();
+    edit_sites(move || delete_site(&site)).await?;
+    info!(site = %name, "Site removed through the control API");
+    send(&state, ApiCommand::ReloadSites)
+}
+
+async fn change_settings(
+    State(state): State<HttpState>,
+    headers: HeaderMap,
+    Json(change): Json<SettingsChange>,
+) -> ApiResult {
+    authorize(&state, &headers)?;
+    let payload = match change.payload {
+        Some(size) => Some(Payload::from_size(size).ok_or((
+            StatusCode::BAD_REQUEST,
+            "Payload must be 32, 64, 128, 256, 512 or 1024 bytes".to_string(),
+        ))?),
+        None => None,
+    };
+    if let Some(timeout) = change.timeout {
+        send(&state, ApiCommand::SetTimeout(timeout))?;
+    }
+    if let Some(payload) = payload {
+        send(&state, ApiCommand::SetPayload(payload))?;
+    }
+    Ok(StatusCode::ACCEPTED)
+}
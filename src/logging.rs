@@ -0,0 +1,25 @@
+use super::*;
+
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{Builder, Rotation};
+use tracing_subscriber::EnvFilter;
+
+/// Sends log output to a new file in the log folder each day.  Keep the guard alive until exit or
+/// buffered lines are lost.  None if the log folder can't be used.
+pub fn init_logging(settings: &Settings) -> Option<WorkerGuard> {
+    let appender = Builder::new()
+        .rotation(Rotation::DAILY)
+        .filename_prefix("mhusd_site_monitor")
+        .filename_suffix("log")
+        .max_log_files(settings.log_files)
+        .build(&settings.log_dir)
+        .ok()?;
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+    let filter = EnvFilter::try_new(&settings.log_level).unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer)
+        .with_ansi(false)
+        .init();
+    Some(guard)
+}
@@ -1,9 +1,43 @@
 #![windows_subsystem = "windows"]
+pub mod cli;
+pub mod discovery;
+pub mod editor;
+pub mod export;
+pub mod groups;
+pub mod http;
+pub mod logging;
+pub mod metrics;
 pub mod model;
+pub mod mtr;
+pub mod outages;
+pub mod records;
+pub mod report;
+pub mod scheduler;
+pub mod settings;
+pub mod sites_file;
+pub mod status;
+pub mod trace;
 pub mod views;
 pub mod worker;
 
+pub use crate::cli::*;
+pub use crate::discovery::*;
+pub use crate::editor::*;
+pub use crate::export::*;
+pub use crate::groups::*;
+pub use crate::http::*;
+pub use crate::logging::*;
+pub use crate::metrics::*;
 pub use crate::model::*;
+pub use crate::mtr::*;
+pub use crate::outages::*;
+pub use crate::records::*;
+pub use crate::report::*;
+pub use crate::scheduler::*;
+pub use crate::settings::*;
+pub use crate::sites_file::*;
+pub use crate::status::*;
+pub use crate::trace::*;
 pub use crate::views::*;
 pub use crate::worker::*;
 
@@ -15,18 +49,31 @@ pub use std::{
     sync::mpsc,
 };
 
-pub use chrono::{DateTime, Local};
+pub use chrono::{DateTime, Local, NaiveDate};
+pub use clap::Parser;
 pub use rand::random;
+pub use serde::{Deserialize, Serialize};
 pub use surge_ping::{Client, Config, IcmpPacket, PingIdentifier, PingSequence, ICMP};
+pub use tracing::{debug, error, info, warn};
 pub use vizia::prelude::*;
 
 fn main() {
-    // Set up communications channel for data to get from GUI thread to tokio thread.
-    let (vizia_tx, tokio_rx) = mpsc::channel::<TokioEvent>(); // Listens for data/events from GUI thread.;
+    // Command line commands run without the GUI.  Hook up the terminal first, clap prints too (help, bad arguments).
+    if std::env::args_os().len() > 1 {
+        attach_console();
+    }
+    if let Some(command) = Cli::parse().command {
+        std::process::exit(run_command(command));
+    }
 
-    // Spawn the tokio thread
-    let _tokio_handle = std::thread::spawn(|| tokio_main(tokio_rx));
+    // Keep the guard until exit so buffered log lines get written.
+    let _log_guard = init_logging(&read_settings());
+    info!(version = env!("CARGO_PKG_VERSION"), "Starting up");
 
-    // GUI blocks on main thread.
-    vizia_main(vizia_tx);
+    // GUI blocks on main thread.  It starts the tokio thread once it has a ContextProxy to hand over.
+    if let Some(supervisor) = vizia_main() {
+        // Closing the window sent Shutdown, let the tokio thread finish what it's writing.
+        wait_for_worker(supervisor);
+    }
+    info!("Exiting");
 }
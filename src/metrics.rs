@@ -0,0 +1,284 @@
+use super::*;
+
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+/// Number of recent probes the loss ratio is worked out over.
+pub const LOSS_WINDOW: usize = 20;
+
+/// Last result for one of a site's addresses.
+#[derive(Default, Clone, Copy)]
+pub struct FamilyMetrics {
+    pub up: bool,
+    pub last_rtt: Option<Duration>,
+}
+
+/// Running counters for one site, as exported to Prometheus.
+#[derive(Default)]
+pub struct SiteMetrics {
+    pub group: String,
+    pub last_rtt: Option<Duration>,
+    pub up: bool,
+    pub ipv4: FamilyMetrics,
+    pub ipv6: Option<FamilyMetrics>, // Dual-stack sites only.
+    pub sent: u64,
+    pub failed: u64,
+    pub recent: VecDeque<bool>,        // true for a failed probe.
+    pub last: Option<PingResponse>,    // Latest result, to check its parent against.
+    pub seen: Option<DateTime<Local>>, // When the latest result came in, or the site was added.
+    pub parent_down: bool, // These two are worked out when the metrics are rendered, like the GUI does.
+    pub stale: bool,
+}
+impl SiteMetrics {
+    pub fn add(&mut self, response: &PingResponse, now: DateTime<Local>) {
+        // Late answer to an earlier ping.
+        if self
+            .last
+            .as_ref()
+            .is_some_and(|last| response.round <= last.round)
+        {
+            return;
+        }
+        self.last = Some(response.clone());
+        self.seen = Some(now);
+        self.sent += 1;
+        self.up = !response.is_err();
+        // Round trip times hold their last value through an outage, mhusd_site_up says it's down.
+        self.last_rtt = response.rtt().or(self.last_rtt);
+        self.ipv4 = FamilyMetrics {
+            up: response.status == PingStatus::Up,
+            last_rtt: response.response.or(self.ipv4.last_rtt),
+        };
+        let last_v6 = self.ipv6.and_then(|f| f.last_rtt);
+        self.ipv6 = response.status_v6.map(|status| FamilyMetrics {
+            up: status == PingStatus::Up,
+            last_rtt: response.response_v6.or(last_v6),
+        });
+        if response.is_err() {
+            self.failed += 1;
+        }
+        self.recent.push_back(response.is_err());
+        if self.recent.len() > LOSS_WINDOW {
+            self.recent.pop_front();
+        }
+    }
+
+    pub fn loss_ratio(&self) -> f64 {
+        if self.recent.is_empty() {
+            return 0.0;
+        }
+        self.recent.iter().filter(|failed| **failed).count() as f64 / self.recent.len() as f64
+    }
+}
+
+struct MetricsInner {
+    config: BTreeMap<String, SiteConfig>,
+    interval: u64, // Seconds between probes for sites without their own interval.
+    sites: BTreeMap<String, SiteMetrics>,
+}
+impl Default for MetricsInner {
+    fn default() -> Self {
+        MetricsInner {
+            config: BTreeMap::new(),
+            interval: DEFAULT_INTERVAL,
+            sites: BTreeMap::new(),
+        }
+    }
+}
+impl MetricsInner {
+    /// Flags sites behind a failing parent, and ones with no result for STALE_INTERVALS intervals.
+    fn mark(&mut self, now: DateTime<Local>) {
+        let mut latest: Vec<PingResponse> =
+            self.sites.values().filter_map(|m| m.last.clone()).collect();
+        mark_unreachable(&mut latest, &self.config);
+        for (name, site) in &mut self.sites {
+            site.parent_down = latest.iter().any(|r| r.name == *name && r.parent_down);
+            let interval = self
+                .config
+                .get(name)
+                .and_then(|c| c.interval)
+                .unwrap_or(self.interval);
+            let limit = chrono::TimeDelta::seconds((interval * STALE_INTERVALS) as i64);
+            site.stale = site.seen.is_some_and(|seen| now - seen > limit);
+        }
+    }
+}
+
+/// Metrics for every site, shared between the result stream and the web server.
+#[derive(Clone, Default)]
+pub struct MetricsStore(Arc<Mutex<MetricsInner>>);
+impl MetricsStore {
+    /// Brings the store in line with sites.json.  Removed sites are dropped, groups are updated.
+    pub fn set_sites(&self, sites: &BTreeMap<String, SiteConfig>) {
+        let mut inner = self.0.lock().unwrap();
+        inner.sites.retain(|name, _| sites.contains_key(name));
+        let now = Local::now();
+        for (name, config) in sites {
+            let site = inner.sites.entry(name.clone()).or_default();
+            site.group = config.group.clone().unwrap_or_default();
+            // New sites get STALE_INTERVALS intervals for their first result.
+            site.seen.get_or_insert(now);
+        }
+        inner.config = sites.clone();
+    }
+
+    pub fn set_interval(&self, interval: u64) {
+        self.0.lock().unwrap().interval = interval;
+    }
+
+    pub fn add(&self, response: &PingResponse) {
+        if let Some(site) = self.0.lock().unwrap().sites.get_mut(&response.name) {
+            site.add(response, Local::now());
+        }
+    }
+
+    /// Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut inner = self.0.lock().unwrap();
+        inner.mark(Local::now());
+        let store = &inner.sites;
+        let mut out = String::new();
+        write_metric(&mut out, store, &SITE_RTT, |m| {
+            m.last_rtt.map(|r| r.as_secs_f64())
+        });
+        write_metric(&mut out, store, &SITE_UP, |m| {
+            (m.sent > 0).then(|| m.up as u8 as f64)
+        });
+        write_metric(&mut out, store, &SITE_UNREACHABLE, |m| {
+            (m.sent > 0).then(|| m.parent_down as u8 as f64)
+        });
+        write_metric(&mut out, store, &SITE_STALE, |m| Some(m.stale as u8 as f64));
+        write_family_metric(&mut out, store, &FAMILY_UP, |f| Some(f.up as u8 as f64));
+        write_family_metric(&mut out, store, &FAMILY_RTT, |f| {
+            f.last_rtt.map(|r| r.as_secs_f64())
+        });
+        write_metric(&mut out, store, &LOSS_RATIO, |m| Some(m.loss_ratio()));
+        write_metric(&mut out, store, &PROBES_SENT, |m| Some(m.sent as f64));
+        write_metric(&mut out, store, &PROBES_FAILED, |m| Some(m.failed as f64));
+        out
+    }
+}
+
+/// A metric family's name, Prometheus type & help text.
+struct Metric {
+    name: &'static str,
+    kind: &'static str,
+    help: &'static str,
+}
+impl Metric {
+    fn header(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} {}", self.name, self.kind);
+    }
+}
+
+const SITE_RTT: Metric = Metric {
+    name: "mhusd_site_rtt_seconds",
+    kind: "gauge",
+    help: "Round trip time of the last successful probe.  Keeps its value while the site is down.",
+};
+const SITE_UP: Metric = Metric {
+    name: "mhusd_site_up",
+    kind: "gauge",
+    help: "1 if the last probe got a reply, 0 if not.",
+};
+const SITE_UNREACHABLE: Metric = Metric {
+    name: "mhusd_site_unreachable",
+    kind: "gauge",
+    help: "1 if the site is down because its parent is, 0 if not.",
+};
+const SITE_STALE: Metric = Metric {
+    name: "mhusd_site_stale",
+    kind: "gauge",
+    help: "1 if there's been no result for the site in the last few intervals, 0 if not.",
+};
+const FAMILY_UP: Metric = Metric {
+    name: "mhusd_site_family_up",
+    kind: "gauge",
+    help: "1 if the last probe to the address got a reply, 0 if not.",
+};
+const FAMILY_RTT: Metric = Metric {
+    name: "mhusd_site_family_rtt_seconds",
+    kind: "gauge",
+    help: "Round trip time of the last successful probe to the address.  Keeps its value while it's down.",
+};
+const LOSS_RATIO: Metric = Metric {
+    name: "mhusd_site_loss_ratio",
+    kind: "gauge",
+    help: "Fraction of recent probes that got no reply.",
+};
+const PROBES_SENT: Metric = Metric {
+    name: "mhusd_probes_sent_total",
+    kind: "counter",
+    help: "Probes sent to the site.",
+};
+const PROBES_FAILED: Metric = Metric {
+    name: "mhusd_probes_failed_total",
+    kind: "counter",
+    help: "Probes to the site that failed.",
+};
+
+/// Writes one metric family, a sample per site.
+fn write_metric(
+    out: &mut String,
+    store: &BTreeMap<String, SiteMetrics>,
+    metric: &Metric,
+    value: impl Fn(&SiteMetrics) -> Option<f64>,
+) {
+    metric.header(out);
+    for (site, m) in store {
+        if let Some(v) = value(m) {
+            let _ = writeln!(
+                out,
+                "{}{{site=\"{}\",group=\"{}\"}} {v}",
+                metric.name,
+                escape_label(site),
+                escape_label(&m.group)
+            );
+        }
+    }
+}
+
+/// Writes one metric family with a sample per address, labelled `family="ipv4"` or `family="ipv6"`.
+/// Sites that haven't been probed yet are left out.
+fn write_family_metric(
+    out: &mut String,
+    store: &BTreeMap<String, SiteMetrics>,
+    metric: &Metric,
+    value: impl Fn(&FamilyMetrics) -> Option<f64>,
+) {
+    metric.header(out);
+    for (site, m) in store.iter().filter(|(_, m)| m.sent > 0) {
+        let families = [("ipv4", Some(m.ipv4)), ("ipv6", m.ipv6)];
+        for (family, f) in families {
+            if let Some(v) = f.as_ref().and_then(&value) {
+                let _ = writeln!(
+                    out,
+                    "{}{{site=\"{}\",group=\"{}\",family=\"{family}\"}} {v}",
+                    metric.name,
+                    escape_label(site),
+                    escape_label(&m.group)
+                );
+            }
+        }
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Feeds every ping result into the metrics store.
+pub async fn record_metrics(store: MetricsStore, mut rx: broadcast::Receiver<PingResponse>) {
+    loop {
+        match rx.recv().await {
+            Ok(response) => store.add(&response),
+            Err(broadcast::error::RecvError::Lagged(_)) => continue, // Dropped a few, counters catch up next round.
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
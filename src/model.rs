@@ -10,6 +10,19 @@ pub enum Payload {
     Giant,
 }
 impl Payload {
+    /// Payload for a size in bytes, if it's one of the sizes on offer.
+    pub fn from_size(size: usize) -> Option<Payload> {
+        match size {
+            32 => Some(Payload::Tiny),
+            64 => Some(Payload::Small),
+            128 => Some(Payload::Medium),
+            256 => Some(Payload::Large),
+            512 => Some(Payload::Huge),
+            1024 => Some(Payload::Giant),
+            _ => None,
+        }
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         match self {
             Payload::Tiny => Vec::from([0; 32]),
@@ -35,27 +48,154 @@ impl std::fmt::Display for Payload {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SortMode {
+    Name,
+    Latency,
+    Status,
+    LastChange,
+}
+impl std::fmt::Display for SortMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match *self {
+            SortMode::Name => "Name",
+            SortMode::Latency => "Latency",
+            SortMode::Status => "Status",
+            SortMode::LastChange => "Changed",
+        };
+        write!(f, "{str}")
+    }
+}
+
+/// Orders sites for display.  Name order is kept as the tiebreaker.  
+pub fn sort_sites(
+    sites: &mut [PingResponse],
+    mode: SortMode,
+    last_change: &BTreeMap<String, DateTime<Local>>,
+) {
+    sites.sort_by(|a, b| a.name.cmp(&b.name));
+    match mode {
+        SortMode::Name => {}
+        // Timeouts first, then slowest first.
+        SortMode::Latency => sites.sort_by(|a, b| {
+            b.rtt()
+                .is_none()
+                .cmp(&a.rtt().is_none())
+                .then(b.rtt().cmp(&a.rtt()))
+        }),
+        // Down, then unreachable, then up.
+        SortMode::Status => sites.sort_by_key(|s| match (s.is_err(), s.parent_down) {
+            (true, false) => 0,
+            (true, true) => 1,
+            (false, _) => 2,
+        }),
+        // Most recent change first.
+        SortMode::LastChange => {
+            sites.sort_by(|a, b| last_change.get(&b.name).cmp(&last_change.get(&a.name)))
+        }
+    }
+}
+
+/// Width of a site tile in the grid layout, including margins.  
+pub const TILE_WIDTH: f32 = 180.0;
+
+/// Sites with no result for this many of their intervals are shown as stale.
+pub const STALE_INTERVALS: u64 = 3;
+
 /// Used for sending signals to Tokio thread via mspc channel.  
 #[derive(Clone)]
 pub enum TokioEvent {
     EventProxy(ContextProxy),
     RefreshSites,
-    TimerElapsed,
+    ProbeNow,
     PayloadChanged(Payload),
     TimeoutChanged(u64),
+    IntervalChanged(u64),
+    Traceroute(String, u64), // Site & trace id.
+    Discover(String),        // Scan a CIDR range for hosts that answer.
+    StopDiscovery,           // Cancel the running scan.
+    Api(ApiCommand),
+    Shutdown, // GUI is closing, stop the runtime.
+}
+
+/// Commands from the web control API.  Passed on to the GUI so it stays in sync, then back like any other change.  
+#[derive(Clone, Debug)]
+pub enum ApiCommand {
+    Refresh,
+    ReloadSites,
+    SetTimeout(u64),
+    SetPayload(Payload),
+}
+impl ApiCommand {
+    pub fn to_vizia_event(&self) -> ViziaEvent {
+        match self {
+            ApiCommand::Refresh => ViziaEvent::RefreshNow,
+            ApiCommand::ReloadSites => ViziaEvent::RefreshSites,
+            ApiCommand::SetTimeout(t) => ViziaEvent::TimeoutDurationChanged(*t),
+            ApiCommand::SetPayload(p) => ViziaEvent::PayloadChanged(*p),
+        }
+    }
 }
 
 /// Application events.  Events can be sent from Tokio thread via ContextProxy.  
 pub enum ViziaEvent {
-    TimerIncrement,              // 1 second increments.
-    TimerReset,                  // Sent when timer reaches 0.
-    PingResponse(PingResponse),  // Sent from tokio thread.
-    MenuTogglePressed,           // Show/hide menu pane.
-    TimerDurationChanged(i32),   // Change the timer duration.
-    RefreshSites,                // Reloads sites.json.
-    AverageTogglePressed,        // Toggle between display averages, current ping.
-    PayloadChanged(Payload),     // Change payload
-    TimeoutDurationChanged(u64), // Change the timeout duration.
+    RefreshNow,                      // Probe every site now.
+    PingResponse(PingResponse),      // Sent from tokio thread.
+    MenuTogglePressed,               // Show/hide menu pane.
+    TimerDurationChanged(i32),       // Change the default probe interval.
+    RefreshSites,                    // Reloads sites.json.
+    AverageTogglePressed,            // Toggle between display averages, current ping.
+    PayloadChanged(Payload),         // Change payload
+    TimeoutDurationChanged(u64),     // Change the timeout duration.
+    TracePressed(String),            // Start a traceroute to the named site.
+    TraceHop(u64, TraceHop),         // Sent from tokio thread, one per hop, by trace id.
+    TraceFinished(u64, String),      // Sent from tokio thread when done, with any error.
+    TraceClosed,                     // Hide the trace panel.
+    MtrRound(String, Vec<TraceHop>), // Sent from tokio thread, one round of hop probes.
+    MtrTogglePressed,                // Show/hide hop statistics.
+    GroupTogglePressed(String),      // Collapse/expand a group in the site list.
+    SortChanged(SortMode),           // Change the site list order.
+    FilterChanged(String),           // Only show sites whose name contains this.
+    ProblemsTogglePressed,           // Only show sites that are failing.
+    TileTogglePressed,               // Toggle between rows and a grid of tiles.
+    ListWidthChanged(f32),           // Site list was resized, refit the tiles.
+    ExportFromChanged(String),       // First day of the export range.
+    ExportToChanged(String),         // Last day of the export range.
+    ExportPressed(ExportFormat),     // Export history for the range.
+    ExportFinished(String),          // Sent when the export is done, with what happened.
+    LogTogglePressed,                // Show/hide the event log.
+    LogFilterChanged(String),        // Only show events containing this.
+    LogExportPressed,                // Write the event log out as CSV.
+    WorkerStopped,                   // Sent from the supervisor when the tokio thread dies.
+    ProbeStats(usize, u64),          // Sent from tokio thread, pings in flight & skipped so far.
+    StaleCheck,                      // Look for sites that haven't had a result in a while.
+    PingUnavailable(String), // Sent from tokio thread when it isn't allowed to ping, with how to fix it.
+    DiscoveryRangeChanged(String), // CIDR range to scan.
+    DiscoveryPressed,        // Start a discovery scan.
+    DiscoveryHost(FoundHost), // Sent from tokio thread, one per host that answered.
+    DiscoveryFinished(String), // Sent from tokio thread when the scan is done, with what happened.
+    DiscoveryToggled(usize), // Select/deselect a found host for adding.
+    DiscoveryAddPressed,     // Add the selected hosts to sites.json.
+    DiscoveryClosed,         // Hide the discovery panel, stopping the scan if it's still running.
+    DiscoveryStopPressed,    // Cancel the running scan.
+    EditorPicked(usize),     // Load a site into the site editor.
+    EditorNewPressed,        // Empty the site editor for a new site.
+    // A site editor text field was edited.
+    EditorChanged(EditorField, String),
+    EditorMtrToggled,      // Toggle hop monitoring for the site being edited.
+    EditorDisabledToggled, // Toggle whether the site being edited is monitored.
+    EditorSavePressed,     // Write the site editor's site to sites.json.
+    EditorDeletePressed,   // Remove the site being edited from sites.json.
+    // Sent from the supervisor with the new thread's channel.
+    WorkerRestarted(mpsc::Sender<TokioEvent>),
+}
+
+/// Counts every site as just seen, so ones that never report go stale after a while.
+pub fn start_last_seen(
+    config: &BTreeMap<String, SiteConfig>,
+    time: DateTime<Local>,
+) -> BTreeMap<String, DateTime<Local>> {
+    config.keys().map(|name| (name.clone(), time)).collect()
 }
 
 /// Populates a Vec of SiteAverages
@@ -67,31 +207,351 @@ pub fn start_history(sites: &Vec<PingResponse>) -> Vec<SiteAverage> {
     sites_averages
 }
 
+/// Options for a single site in sites.json.
+#[derive(Deserialize, Clone)]
+pub struct SiteConfig {
+    pub address: IpAddr,
+    #[serde(default)]
+    pub address_v6: Option<IpAddr>, // Dual-stack sites, pinged alongside the IPv4 `address`.
+    #[serde(default)]
+    pub mtr: bool, // Continuously monitor every hop on the way to this site.
+    #[serde(default)]
+    pub group: Option<String>, // Section of the site list this site is shown under.
+    #[serde(default)]
+    pub parent: Option<String>, // Site this one is reached through, e.g. the school's router.
+    #[serde(default)]
+    pub interval: Option<u64>, // Seconds between probes, instead of the refresh interval.
+    #[serde(default)]
+    pub disabled: bool, // Kept in sites.json but not monitored.
+    #[serde(flatten)]
+    pub socket: SocketOptions, // Overrides the ones in settings.json.
+}
+
+/// A sites.json entry.  Either a bare address, or an object for sites that need options.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SiteEntry {
+    Address(IpAddr),
+    Detailed(SiteConfig),
+}
+impl From<SiteEntry> for SiteConfig {
+    fn from(entry: SiteEntry) -> Self {
+        match entry {
+            SiteEntry::Address(address) => SiteConfig {
+                address,
+                address_v6: None,
+                mtr: false,
+                group: None,
+                parent: None,
+                interval: None,
+                disabled: false,
+                socket: SocketOptions::default(),
+            },
+            SiteEntry::Detailed(config) => config,
+        }
+    }
+}
+
+impl SiteConfig {
+    /// Catches what serde can't, e.g. an IPv4 `address_v6`.
+    pub fn validate(&self) -> Result<(), String> {
+        match self.address_v6 {
+            Some(v6) if !v6.is_ipv6() || !self.address.is_ipv4() => Err(format!(
+                "address_v6 ({v6}) must be an IPv6 address, with an IPv4 address ({}) as address",
+                self.address
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
 /// Maps sites.json.  Panics if unable to read sites.json or unable to parse the data within the file.  
-pub fn read_sites() -> BTreeMap<String, IpAddr> {
-    let data = fs::read_to_string(Path::new("sites.json")).expect("Unable to read file");
-    serde_json::from_str(&data).expect("Unable to deserialize data")
+pub fn read_sites() -> BTreeMap<String, SiteConfig> {
+    load_sites().unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// Maps sites.json, for callers that can cope with it being missing or broken.  Disabled sites are left out.  
+pub fn load_sites() -> Result<BTreeMap<String, SiteConfig>, String> {
+    let data = fs::read_to_string(Path::new("sites.json"))
+        .map_err(|e| format!("Unable to read file: {e}"))?;
+    let entries: BTreeMap<String, SiteEntry> =
+        serde_json::from_str(&data).map_err(|e| format!("Unable to deserialize data: {e}"))?;
+    let mut sites = site_configs(entries)?;
+    sites.retain(|_, config| !config.disabled);
+    Ok(sites)
+}
+
+/// Checks every site, and that their parents make sense together.  Disabled sites are included,
+/// they're still in the file.
+fn site_configs(
+    entries: BTreeMap<String, SiteEntry>,
+) -> Result<BTreeMap<String, SiteConfig>, String> {
+    let sites: BTreeMap<String, SiteConfig> = entries
+        .into_iter()
+        .map(|(name, entry)| (name, entry.into()))
+        .collect();
+    for (name, config) in &sites {
+        config.validate().map_err(|e| format!("{name}: {e}"))?;
+    }
+    check_parents(&sites)?;
+    Ok(sites)
+}
+
+/// Every parent has to be a site in the file, and following parents up can't come back around.
+fn check_parents(sites: &BTreeMap<String, SiteConfig>) -> Result<(), String> {
+    for (name, config) in sites {
+        let mut chain = vec![name.as_str()];
+        let mut parent = config.parent.as_deref();
+        while let Some(next) = parent {
+            if chain.contains(&next) {
+                chain.push(next);
+                return Err(format!(
+                    "Parents go round in a loop: {}",
+                    chain.join(" -> ")
+                ));
+            }
+            let Some(config) = sites.get(next) else {
+                return Err(format!(
+                    "{}: no site named {next} to be its parent",
+                    chain[chain.len() - 1]
+                ));
+            };
+            chain.push(next);
+            parent = config.parent.as_deref();
+        }
+    }
+    Ok(())
+}
+
+/// Adds or replaces a site in sites.json.  `entry` is either an address or an options object.  
+pub fn write_site(name: &str, entry: serde_json::Value) -> Result<(), String> {
+    check_entry(&entry)?;
+    edit_sites_file(|sites| {
+        sites.insert(name.to_string(), entry);
+        Ok(())
+    })
+}
+
+/// Replaces the site called `old` with `entry` under `name`, in the same place in the file.  
+/// An empty `old` adds a new site.  Won't overwrite a different site that already has `name`.  
+/// Sites with `old` as their parent are moved over to `name`.  
+pub fn replace_site(old: &str, name: &str, entry: serde_json::Value) -> Result<(), String> {
+    check_entry(&entry)?;
+    edit_sites_file(|sites| {
+        if old != name && sites.contains_key(name) {
+            return Err(format!("There's already a site named {name}"));
+        }
+        if old.is_empty() {
+            sites.insert(name.to_string(), entry);
+            return Ok(());
+        }
+        if !sites.contains_key(old) {
+            return Err(format!("No site named {old}"));
+        }
+        let children = children_of(sites, old);
+        let disabled = entry.get("disabled").and_then(serde_json::Value::as_bool) == Some(true);
+        if disabled && !children.is_empty() {
+            // Disabled sites aren't loaded, the children would lose their parent without a word.
+            return Err(format!(
+                "{old} can't be disabled while it's the parent of {}",
+                children.join(", ")
+            ));
+        }
+        for child in &children {
+            if let Some(serde_json::Value::Object(fields)) = sites.get_mut(child) {
+                fields.insert("parent".to_string(), name.into());
+            }
+        }
+        *sites = std::mem::take(sites)
+            .into_iter()
+            .map(|(key, value)| {
+                if key == old {
+                    (name.to_string(), entry.clone())
+                } else {
+                    (key, value)
+                }
+            })
+            .collect();
+        Ok(())
+    })
+}
+
+/// Removes a site from sites.json.  Refused while other sites have it as their parent.  
+pub fn delete_site(name: &str) -> Result<(), String> {
+    edit_sites_file(|sites| {
+        let children = children_of(sites, name);
+        if !children.is_empty() {
+            return Err(format!(
+                "{name} is the parent of {}, change their parent first",
+                children.join(", ")
+            ));
+        }
+        match sites.shift_remove(name) {
+            Some(_) => Ok(()),
+            None => Err(format!("No site named {name}")),
+        }
+    })
+}
+
+/// Sites in sites.json whose parent is `name`.
+fn children_of(sites: &serde_json::Map<String, serde_json::Value>, name: &str) -> Vec<String> {
+    sites
+        .iter()
+        .filter(|(_, entry)| entry.get("parent").and_then(serde_json::Value::as_str) == Some(name))
+        .map(|(child, _)| child.clone())
+        .collect()
+}
+
+/// A single site's entry as it is in sites.json, unknown fields and all.  
+pub fn read_site_entry(name: &str) -> Result<serde_json::Value, String> {
+    let (_, sites) = read_sites_file()?;
+    sites
+        .get(name)
+        .cloned()
+        .ok_or_else(|| format!("No site named {name}"))
+}
+
+fn check_entry(entry: &serde_json::Value) -> Result<(), String> {
+    let config: SiteConfig = serde_json::from_value::<SiteEntry>(entry.clone())
+        .map_err(|e| format!("Invalid site: {e}"))?
+        .into();
+    config.validate().map_err(|e| format!("Invalid site: {e}"))
+}
+
+fn read_sites_file() -> Result<(String, serde_json::Map<String, serde_json::Value>), String> {
+    let data = fs::read_to_string(Path::new("sites.json")).map_err(|e| e.to_string())?;
+    let sites = serde_json::from_str(&data).map_err(|e| e.to_string())?;
+    Ok((data, sites))
+}
+
+/// Held while sites.json is being read, changed and written back, so two edits can't lose each other's changes.
+static SITES_FILE_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+fn edit_sites_file(
+    edit: impl FnOnce(&mut serde_json::Map<String, serde_json::Value>) -> Result<(), String>,
+) -> Result<(), String> {
+    // Nothing is left half done if an edit panics, carry on.
+    let _lock = SITES_FILE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let (original, mut sites) = read_sites_file()?;
+    let before = sites.clone();
+    edit(&mut sites)?;
+    // Don't write anything the next reload would refuse, e.g. a parent that isn't there.
+    serde_json::from_value(serde_json::Value::Object(sites.clone()))
+        .map_err(|e| e.to_string())
+        .and_then(site_configs)?;
+    // Only the sites that changed are rewritten, the rest keep their formatting.
+    let data = match splice_sites(&original, &before, &sites) {
+        Some(data) => data.into_bytes(),
+        None => {
+            warn!("Couldn't edit sites.json in place, reformatting the whole file");
+            format_sites(&original, &sites)?
+        }
+    };
+    // Write a temporary file first so sites.json is never left half written.  Named uniquely so
+    // another copy of the monitor (or the command line) can't write over it.
+    let tmp = format!(
+        "sites.json.{}-{:08x}.tmp",
+        std::process::id(),
+        random::<u32>()
+    );
+    fs::write(Path::new(&tmp), data).map_err(|e| e.to_string())?;
+    fs::rename(Path::new(&tmp), Path::new("sites.json")).map_err(|e| {
+        let _ = fs::remove_file(Path::new(&tmp));
+        e.to_string()
+    })
+}
+
+/// The whole of sites.json, pretty printed with the file's own indentation.
+fn format_sites(
+    original: &str,
+    sites: &serde_json::Map<String, serde_json::Value>,
+) -> Result<Vec<u8>, String> {
+    let indent = original
+        .lines()
+        .skip(1)
+        .find(|l| !l.trim().is_empty())
+        .map(|l| &l[..l.len() - l.trim_start().len()])
+        .filter(|i| !i.is_empty())
+        .unwrap_or("  ");
+    let mut data = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+    sites
+        .serialize(&mut serde_json::Serializer::with_formatter(
+            &mut data, formatter,
+        ))
+        .map_err(|e| e.to_string())?;
+    if original.ends_with('\n') {
+        data.push(b'\n');
+    }
+    Ok(data)
+}
+
+/// Every site in sites.json in file order, disabled ones too.  
+pub fn list_sites() -> Result<Vec<ListedSite>, String> {
+    let (_, sites) = read_sites_file()?;
+    Ok(sites
+        .iter()
+        .map(|(name, entry)| ListedSite {
+            name: name.clone(),
+            disabled: entry
+                .get("disabled")
+                .and_then(serde_json::Value::as_bool)
+                .unwrap_or(false),
+        })
+        .collect())
 }
 
 /// Converts data from read_sites into useful data for vizia_main AppData
-pub fn sites_to_pings(sites: BTreeMap<String, IpAddr>) -> Vec<PingResponse> {
+pub fn sites_to_pings(sites: BTreeMap<String, SiteConfig>) -> Vec<PingResponse> {
     let mut map = Vec::new();
     for (name, _) in sites {
         map.push(PingResponse {
             name,
             response: None,
-            is_err: true,
+            status: PingStatus::Pending,
+            error: "Waiting for the first result".to_string(),
+            response_v6: None,
+            status_v6: None,
+            parent_down: false,
+            stale: false,
+            round: 0,
         });
     }
     map
 }
 
+/// Flags failing sites whose parent is also failing, down or unreachable itself.  A parent that
+/// answers means the way to the site is fine, so the site's own failure is real.
+pub fn mark_unreachable(sites: &mut [PingResponse], config: &BTreeMap<String, SiteConfig>) {
+    let failing: Vec<String> = sites
+        .iter()
+        .filter(|s| s.is_err())
+        .map(|s| s.name.clone())
+        .collect();
+    for site in sites.iter_mut() {
+        let parent = config.get(&site.name).and_then(|c| c.parent.as_ref());
+        site.parent_down = site.is_err() && parent.is_some_and(|p| failing.contains(p));
+    }
+}
+
+/// Whether the site's parent hasn't had a result yet.
+fn parent_pending(
+    site: &PingResponse,
+    sites: &[PingResponse],
+    config: &BTreeMap<String, SiteConfig>,
+) -> bool {
+    let Some(parent) = config.get(&site.name).and_then(|c| c.parent.as_ref()) else {
+        return false;
+    };
+    sites
+        .iter()
+        .any(|s| s.name == *parent && s.status == PingStatus::Pending)
+}
+
 /// Application data / model.  
 #[derive(Lens, Clone)]
 pub struct AppData {
     pub sites: Vec<PingResponse>,
-    pub timer: Timer,
-    pub timer_count: i32,
     pub tx: mpsc::Sender<TokioEvent>,
     pub menu_visible: bool,
     pub timer_duration: i32,
@@ -100,71 +560,499 @@ pub struct AppData {
     pub history: Vec<SiteAverage>,
     pub payload: Payload,
     pub timeout: u64,
+    pub trace: TraceResult,
+    pub show_mtr: bool,
+    pub mtr: Vec<MtrSite>,
+    pub groups: Vec<SiteGroup>,
+    pub config: BTreeMap<String, SiteConfig>,
+    pub collapsed: Vec<String>,
+    pub sort: SortMode,
+    pub filter: String,
+    pub problems_only: bool,
+    pub last_change: BTreeMap<String, DateTime<Local>>,
+    pub logged_state: BTreeMap<String, (bool, bool)>, // (failing, parent down) as last written to the event log.
+    pub last_seen: BTreeMap<String, DateTime<Local>>, // When each site's last result came in.
+    pub tile_layout: bool,
+    pub tile_columns: usize,
+    pub export_from: String,
+    pub export_to: String,
+    pub export_status: String,
+    pub show_log: bool,
+    pub event_log: Vec<LogEntry>,
+    pub shown_log: Vec<LogEntry>,
+    pub log_filter: String,
+    pub log_status: String,
+    pub worker_alive: bool,
+    pub worker_restarts: u32,
+    pub probes_in_flight: usize,
+    pub probes_skipped: u64,
+    pub ping_problem: String,  // Why pings can't be sent, empty if they can.
+    pub sites_problem: String, // Why sites.json couldn't be reloaded, empty if it could.
+    pub discovery: Discovery,
+    pub editor: SiteEditor,
+}
+impl AppData {
+    /// Sends to the tokio thread.  If it's gone, flags it for the health indicator until the supervisor restarts it.
+    pub fn send(&mut self, event: TokioEvent) {
+        if self.tx.send(event).is_err() {
+            if self.worker_alive {
+                error!("Couldn't send to the tokio thread, channel is closed");
+            }
+            self.worker_alive = false;
+        }
+    }
+
+    /// Cancels the discovery scan if one is running.  Hosts found so far stay listed.
+    fn stop_discovery(&mut self) {
+        if !self.discovery.running {
+            return;
+        }
+        info!(range = %self.discovery.range, "Discovery scan stopped");
+        self.send(TokioEvent::StopDiscovery);
+        self.discovery.running = false;
+        self.discovery.status = format!(
+            "Scan stopped, {} hosts found so far",
+            self.discovery.hosts.len()
+        );
+    }
+
+    /// Logs every site whose state changed: going down, coming back, or switching between down & unreachable
+    /// as its parent fails or recovers.  Call after regroup so parent_down is current.  A site's first result
+    /// counts as a change if it's failing.  Failing sites under a parent with no result yet are left until it
+    /// has one, there's no telling down from unreachable before then.
+    pub fn log_changes(&mut self, time: DateTime<Local>) {
+        let mut events = Vec::new();
+        for site in &self.sites {
+            if site.status == PingStatus::Pending
+                || (site.is_err() && parent_pending(site, &self.sites, &self.config))
+            {
+                continue;
+            }
+            let state = (site.is_err(), site.is_err() && site.parent_down);
+            let before = self.logged_state.insert(site.name.clone(), state);
+            match before {
+                Some(before) if before == state => continue,
+                // First result and it's up, nothing to log.
+                None if !state.0 => {
+                    self.last_change.insert(site.name.clone(), time);
+                    continue;
+                }
+                _ => {}
+            }
+            let since = self.last_change.get(&site.name).copied().unwrap_or(time);
+            // Down <-> unreachable is still the same outage.
+            if before.map(|b| b.0) != Some(state.0) {
+                self.last_change.insert(site.name.clone(), time);
+            }
+            let (kind, down_secs) = match state {
+                (false, _) => (OutageKind::Recovered, Some((time - since).num_seconds())),
+                (true, true) => (OutageKind::Unreachable, None),
+                (true, false) => (OutageKind::Down, None),
+            };
+            events.push(OutageEvent {
+                time,
+                site: site.name.clone(),
+                kind,
+                down_secs,
+            });
+        }
+        if events.is_empty() {
+            return;
+        }
+        for event in &events {
+            info!(site = %event.site, kind = ?event.kind, "Status changed");
+            if let Err(e) = append_event(event) {
+                warn!(error = %e, "Couldn't save to the event log");
+            }
+            self.event_log.insert(0, LogEntry::from(event));
+        }
+        self.event_log.truncate(EVENT_LOG_LEN);
+        self.refilter_log();
+    }
+
+    /// Rebuilds the visible event log after new events or a filter change.
+    pub fn refilter_log(&mut self) {
+        let filter = self.log_filter.to_lowercase();
+        self.shown_log = self
+            .event_log
+            .iter()
+            .filter(|e| e.text.to_lowercase().contains(&filter))
+            .cloned()
+            .collect();
+    }
+
+    /// Rebuilds the grouped site list after sites, averages or collapsed groups change.
+    pub fn regroup(&mut self) {
+        mark_unreachable(&mut self.sites, &self.config);
+        let filter = self.filter.to_lowercase();
+        let mut shown: Vec<PingResponse> = self
+            .sites
+            .iter()
+            .filter(|s| !self.problems_only || s.is_err() || s.stale)
+            .filter(|s| s.name.to_lowercase().contains(&filter))
+            .cloned()
+            .collect();
+        sort_sites(&mut shown, self.sort, &self.last_change);
+        self.groups = build_groups(&shown, &self.history, &self.config, &self.collapsed);
+    }
 }
 impl Model for AppData {
     fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
         event.map(|app_event, _| {
             match app_event {
-                ViziaEvent::TimerIncrement => {
-                    self.timer_count -= 1;
-                    if self.timer_count <= 0 {
-                        cx.emit(ViziaEvent::TimerReset);
-                    }
-                }
-                ViziaEvent::TimerReset => {
-                    let _ = self.tx.send(TokioEvent::TimerElapsed); // TODO: Handle potential errors.
-                    self.current_time = Local::now();
-                    self.timer_count = self.timer_duration;
-                }
+                ViziaEvent::RefreshNow => self.send(TokioEvent::ProbeNow),
                 ViziaEvent::PingResponse(response) => {
-                    if let Some(i) = self
+                    // Sites removed since the ping went out, and answers older than what's shown, are dropped.
+                    let Some(i) = self
                         .sites
                         .iter()
                         .position(|site| site.name == response.name)
-                    {
-                        self.sites[i] = response.clone();
-                    } else {
-                        self.sites.push(response.clone());
+                    else {
+                        return;
+                    };
+                    if response.round <= self.sites[i].round {
+                        debug!(site = %response.name, round = response.round, "Discarding out of order result");
+                        return;
                     }
-                    if self.show_average {
-                        if response.is_err {
-                            // Discard error results.
-                            return;
-                        }
+                    let now = Local::now();
+                    self.current_time = now;
+                    self.last_seen.insert(response.name.clone(), now);
+                    self.sites[i] = response.clone();
+                    // Discard error results.  Only the IPv4 address is averaged, IPv6 latency would skew it.
+                    if let Some(rtt) = response.response.filter(|_| self.show_average) {
                         if let Some(pos) = self.history.iter().position(|h| h.name == response.name)
                         {
-                            self.history
-                                .get_mut(pos)
-                                .unwrap()
-                                .add(response.response.unwrap())
+                            self.history.get_mut(pos).unwrap().add(rtt)
                         }
                     }
+                    self.regroup();
+                    self.log_changes(now);
                 }
                 ViziaEvent::MenuTogglePressed => self.menu_visible = !self.menu_visible,
                 ViziaEvent::TimerDurationChanged(t) => {
-                    self.timer_duration = *t;
+                    if *t > 0 {
+                        info!(interval = *t, "Refresh interval changed");
+                        self.timer_duration = *t;
+                        self.send(TokioEvent::IntervalChanged(*t as u64));
+                    }
                 }
                 ViziaEvent::RefreshSites => {
-                    self.sites = sites_to_pings(read_sites());
-                    let _ = self.tx.send(TokioEvent::RefreshSites);
-                    cx.emit(ViziaEvent::TimerReset);
+                    match load_sites() {
+                        Ok(config) => {
+                            self.config = config;
+                            self.sites_problem.clear();
+                        }
+                        Err(e) => {
+                            error!(error = %e, "Couldn't reload sites.json, keeping the current sites");
+                            self.sites_problem = format!("Couldn't reload sites.json: {e}");
+                            return;
+                        }
+                    }
+                    self.sites = sites_to_pings(self.config.clone());
+                    self.last_seen = start_last_seen(&self.config, Local::now());
+                    let config = &self.config;
+                    self.logged_state.retain(|name, _| config.contains_key(name));
+                    self.history = start_history(&self.sites);
+                    self.regroup();
+                    // Keep the hop statistics of sites that are still monitored.
+                    self.mtr.retain(|m| self.config.get(&m.name).is_some_and(|c| c.mtr));
+                    self.editor.reload();
+                    info!(sites = self.config.len(), "Reloaded sites.json");
+                    self.send(TokioEvent::RefreshSites);
+                    cx.emit(ViziaEvent::RefreshNow);
                 }
                 ViziaEvent::AverageTogglePressed => {
                     for h in &mut self.history {
                         h.clear()
                     }
-                    self.show_average = !self.show_average
+                    self.show_average = !self.show_average;
+                    self.regroup();
                 }
                 ViziaEvent::PayloadChanged(p) => {
                     self.payload = *p;
-                    let _ = self.tx.send(TokioEvent::PayloadChanged(self.payload));
+                    info!(payload = %self.payload, "Payload changed");
+                    self.send(TokioEvent::PayloadChanged(self.payload));
                 }
                 ViziaEvent::TimeoutDurationChanged(i) => {
                     self.timeout = *i;
-                    let _ = self.tx.send(TokioEvent::TimeoutChanged(self.timeout));
+                    info!(timeout = self.timeout, "Timeout changed");
+                    self.send(TokioEvent::TimeoutChanged(self.timeout));
+                }
+                ViziaEvent::TracePressed(name) => {
+                    self.trace = TraceResult::new(name.clone(), self.trace.id + 1);
+                    info!(site = %name, "Traceroute requested");
+                    self.send(TokioEvent::Traceroute(name.clone(), self.trace.id));
+                }
+                ViziaEvent::TraceHop(id, hop) => {
+                    // Ignore stragglers from a trace that's since been replaced, even one to the same site.
+                    if self.trace.id == *id {
+                        self.trace.hops.push(hop.clone());
+                    }
+                }
+                ViziaEvent::TraceFinished(id, error) => {
+                    if self.trace.id == *id {
+                        self.trace.running = false;
+                        self.trace.error = error.clone();
+                    }
+                }
+                // Keeps the id so the next trace can't be mistaken for one still running.
+                ViziaEvent::TraceClosed => {
+                    self.trace = TraceResult {
+                        id: self.trace.id,
+                        ..Default::default()
+                    }
+                }
+                ViziaEvent::DiscoveryRangeChanged(range) => self.discovery.range = range.clone(),
+                ViziaEvent::DiscoveryPressed => {
+                    // One scan at a time, results from two would be mixed together.
+                    if self.discovery.running {
+                        self.discovery.shown = true;
+                    } else {
+                        self.discovery = Discovery {
+                            range: self.discovery.range.clone(),
+                            running: true,
+                            shown: true,
+                            status: format!("Scanning {}...", self.discovery.range),
+                            ..Default::default()
+                        };
+                        info!(range = %self.discovery.range, "Discovery scan requested");
+                        self.send(TokioEvent::Discover(self.discovery.range.clone()));
+                        // Nothing will come back to say it's finished.
+                        if !self.worker_alive {
+                            self.discovery.running = false;
+                            self.discovery.status =
+                                "Monitoring isn't running, try again once it restarts".to_string();
+                        }
+                    }
+                }
+                // Stragglers from a stopped scan are ignored.
+                ViziaEvent::DiscoveryHost(host) if self.discovery.running => {
+                    let mut host = host.clone();
+                    host.name = self
+                        .discovery
+                        .site_name(&host.hostname, &host.addr, &self.config);
+                    // Hosts that are already sites are listed, but not picked by default.
+                    host.selected = !self.config.values().any(|c| c.address.to_string() == host.addr);
+                    self.discovery.hosts.push(host);
+                    self.discovery.hosts.sort_by_key(|h| h.addr.parse::<IpAddr>().ok());
+                }
+                ViziaEvent::DiscoveryHost(_) => {}
+                ViziaEvent::DiscoveryFinished(status) => {
+                    self.discovery.running = false;
+                    self.discovery.status = status.clone();
+                }
+                ViziaEvent::DiscoveryStopPressed => self.stop_discovery(),
+                ViziaEvent::DiscoveryToggled(index) => {
+                    if let Some(host) = self.discovery.hosts.get_mut(*index) {
+                        host.selected = !host.selected;
+                    }
+                }
+                ViziaEvent::DiscoveryAddPressed => {
+                    let mut added = 0;
+                    let mut failed = Vec::new();
+                    for host in self.discovery.hosts.iter().filter(|h| h.selected) {
+                        match write_site(&host.name, serde_json::Value::from(host.addr.clone())) {
+                            Ok(()) => added += 1,
+                            Err(e) => {
+                                error!(site = %host.name, error = %e, "Couldn't add discovered host");
+                                failed.push(format!("{}: {e}", host.name));
+                            }
+                        }
+                    }
+                    info!(added, "Added discovered hosts to sites.json");
+                    self.discovery.hosts.retain(|h| !h.selected);
+                    self.discovery.status = if failed.is_empty() {
+                        format!("Added {added} sites")
+                    } else {
+                        format!("Added {added} sites, failed {}", failed.join(", "))
+                    };
+                    if added > 0 {
+                        cx.emit(ViziaEvent::RefreshSites);
+                    }
+                }
+                ViziaEvent::EditorPicked(index) => {
+                    if let Some(site) = self.editor.sites.get(*index).cloned() {
+                        match read_site_entry(&site.name) {
+                            Ok(entry) => self.editor.pick(&site.name, &entry),
+                            Err(e) => self.editor.status = e,
+                        }
+                    }
+                }
+                ViziaEvent::EditorNewPressed => self.editor.clear(),
+                ViziaEvent::EditorChanged(field, text) => self.editor.set(*field, text.clone()),
+                ViziaEvent::EditorMtrToggled => self.editor.mtr = !self.editor.mtr,
+                ViziaEvent::EditorDisabledToggled => self.editor.disabled = !self.editor.disabled,
+                ViziaEvent::EditorSavePressed => match self.editor.save() {
+                    Ok(status) => {
+                        self.editor.status = status;
+                        // Same as pressing "Reload sites.json".
+                        cx.emit(ViziaEvent::RefreshSites);
+                    }
+                    Err(e) => {
+                        warn!(site = %self.editor.name, error = %e, "Couldn't save site");
+                        self.editor.status = e;
+                    }
+                },
+                ViziaEvent::EditorDeletePressed => match self.editor.delete() {
+                    Ok(Some(status)) => {
+                        self.editor.status = status;
+                        cx.emit(ViziaEvent::RefreshSites);
+                    }
+                    Ok(None) => {
+                        self.editor.status =
+                            format!("Press Delete again to remove {}", self.editor.original)
+                    }
+                    Err(e) => {
+                        warn!(site = %self.editor.original, error = %e, "Couldn't delete site");
+                        self.editor.status = e;
+                    }
+                },
+                ViziaEvent::DiscoveryClosed => {
+                    self.stop_discovery();
+                    self.discovery.shown = false;
+                }
+                ViziaEvent::MtrRound(name, round) => {
+                    if let Some(site) = self.mtr.iter_mut().find(|m| m.name == *name) {
+                        site.update(round);
+                    } else {
+                        let mut site = MtrSite::new(name.clone());
+                        site.update(round);
+                        self.mtr.push(site);
+                        self.mtr.sort_by(|a, b| a.name.cmp(&b.name));
+                    }
+                }
+                ViziaEvent::MtrTogglePressed => self.show_mtr = !self.show_mtr,
+                ViziaEvent::GroupTogglePressed(name) => {
+                    if let Some(i) = self.collapsed.iter().position(|c| c == name) {
+                        self.collapsed.remove(i);
+                    } else {
+                        self.collapsed.push(name.clone());
+                    }
+                    self.regroup();
+                }
+                ViziaEvent::SortChanged(mode) => {
+                    self.sort = *mode;
+                    self.regroup();
+                }
+                ViziaEvent::FilterChanged(text) => {
+                    self.filter = text.clone();
+                    self.regroup();
+                }
+                ViziaEvent::ProblemsTogglePressed => {
+                    self.problems_only = !self.problems_only;
+                    self.regroup();
+                }
+                ViziaEvent::ExportFromChanged(text) => self.export_from = text.clone(),
+                ViziaEvent::ExportToChanged(text) => self.export_to = text.clone(),
+                ViziaEvent::ExportPressed(format) => {
+                    let dates = (
+                        NaiveDate::parse_from_str(&self.export_from, "%Y-%m-%d"),
+                        NaiveDate::parse_from_str(&self.export_to, "%Y-%m-%d"),
+                    );
+                    let (Ok(from), Ok(to)) = dates else {
+                        self.export_status = "Dates must be YYYY-MM-DD".to_string();
+                        return;
+                    };
+                    self.export_status = "Exporting...".to_string();
+                    let format = *format;
+                    // Reading a month of history takes a moment, keep it off the GUI thread.
+                    cx.spawn(move |cx| {
+                        let status = match export(from, to, format, &export_base(from, to)) {
+                            Ok(files) => {
+                                let files: Vec<String> =
+                                    files.iter().map(|f| f.display().to_string()).collect();
+                                format!("Exported {}", files.join(", "))
+                            }
+                            Err(e) => {
+                                error!(error = %e, "History export failed");
+                                format!("Export failed: {e}")
+                            }
+                        };
+                        info!(%status, "History export finished");
+                        let _ = cx.emit(ViziaEvent::ExportFinished(status));
+                    });
+                }
+                ViziaEvent::ExportFinished(status) => self.export_status = status.clone(),
+                ViziaEvent::LogTogglePressed => self.show_log = !self.show_log,
+                ViziaEvent::LogFilterChanged(text) => {
+                    self.log_filter = text.clone();
+                    self.refilter_log();
+                }
+                ViziaEvent::LogExportPressed => {
+                    let file = format!("events-{}.csv", Local::now().format("%Y-%m-%d"));
+                    self.log_status = match export_events(Path::new(&file)) {
+                        Ok(()) => format!("Exported {file}"),
+                        Err(e) => {
+                            error!(error = %e, "Event log export failed");
+                            format!("Export failed: {e}")
+                        }
+                    };
+                }
+                ViziaEvent::TileTogglePressed => self.tile_layout = !self.tile_layout,
+                ViziaEvent::ListWidthChanged(width) => {
+                    self.tile_columns = ((*width / TILE_WIDTH) as usize).max(1);
+                }
+                ViziaEvent::WorkerStopped => self.worker_alive = false,
+                ViziaEvent::PingUnavailable(message) => self.ping_problem = message.clone(),
+                ViziaEvent::StaleCheck => {
+                    let now = Local::now();
+                    let mut changed = false;
+                    for site in &mut self.sites {
+                        let interval = self
+                            .config
+                            .get(&site.name)
+                            .and_then(|c| c.interval)
+                            .unwrap_or(self.timer_duration as u64);
+                        let limit = chrono::TimeDelta::seconds((interval * STALE_INTERVALS) as i64);
+                        let stale = self
+                            .last_seen
+                            .get(&site.name)
+                            .is_some_and(|seen| now - *seen > limit);
+                        if site.stale != stale {
+                            if stale {
+                                warn!(site = %site.name, "No results lately, showing as stale");
+                            }
+                            site.stale = stale;
+                            changed = true;
+                        }
+                    }
+                    if changed {
+                        self.regroup();
+                    }
+                }
+                ViziaEvent::ProbeStats(in_flight, skipped) => {
+                    self.probes_in_flight = *in_flight;
+                    self.probes_skipped = *skipped;
+                }
+                ViziaEvent::WorkerRestarted(tx) => {
+                    self.tx = tx.clone();
+                    self.worker_alive = true;
+                    self.worker_restarts += 1;
+                    self.ping_problem.clear(); // The new thread will say if it's still a problem.
+                    // A scan dies with the thread that was running it.
+                    if self.discovery.running {
+                        self.discovery.running = false;
+                        self.discovery.status =
+                            "Scan stopped, the monitoring thread restarted".to_string();
+                    }
+                    // Round numbers start over with the new thread.
+                    for site in &mut self.sites {
+                        site.round = 0;
+                    }
+                    // New thread starts from defaults, give it our settings and a fresh round.
+                    self.send(TokioEvent::PayloadChanged(self.payload));
+                    self.send(TokioEvent::TimeoutChanged(self.timeout));
+                    self.send(TokioEvent::IntervalChanged(self.timer_duration as u64));
+                    cx.emit(ViziaEvent::RefreshNow);
                 }
             }
-        })
+        });
+        // Let the tokio thread stop cleanly instead of being killed mid-write.
+        event.map(|window_event, _| {
+            if let WindowEvent::WindowClose = window_event {
+                self.send(TokioEvent::Shutdown);
+            }
+        });
     }
 }
 
@@ -199,12 +1087,107 @@ impl SiteAverage {
     }
 }
 
+/// What came of a site's last ping.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Data)]
+pub enum PingStatus {
+    Pending,          // No result yet.
+    Up,               // Got an echo reply.
+    Timeout,          // Nothing came back in time.
+    Unreachable,      // A router said the site can't be reached, or there's no route to it.
+    PermissionDenied, // Not allowed to send pings.
+    Failed,           // Anything else.
+}
+impl PingStatus {
+    /// Short text for the response column.
+    pub fn label(&self) -> &'static str {
+        match self {
+            PingStatus::Pending => "Waiting...",
+            PingStatus::Up => "Up",
+            PingStatus::Timeout => "Timeout!",
+            PingStatus::Unreachable => "Unreachable!",
+            PingStatus::PermissionDenied => "No permission!",
+            PingStatus::Failed => "Error!",
+        }
+    }
+}
+
 /// Data structure for site name & ping response.  
 #[derive(Lens, Clone, PartialEq, Data)]
 pub struct PingResponse {
     pub name: String,
     pub response: Option<Duration>,
-    pub is_err: bool,
+    pub status: PingStatus,
+    pub error: String, // What went wrong, for the tooltip.  Empty when up.
+    pub response_v6: Option<Duration>, // Dual-stack sites only, `response` is the IPv4 one.
+    pub status_v6: Option<PingStatus>, // None for sites with a single address.
+    pub parent_down: bool, // Set by the GUI, the site is failing because its parent is.
+    pub stale: bool,   // Set by the GUI, no result for STALE_INTERVALS intervals.
+    pub round: u64,    // Which ping this answers.  Higher is newer, 0 until the first result.
+}
+impl PingResponse {
+    /// Anything but a reply, including no result yet.  Dual-stack sites are only down when both families are.
+    pub fn is_err(&self) -> bool {
+        self.status != PingStatus::Up && self.status_v6 != Some(PingStatus::Up)
+    }
+
+    /// Failing with a result to show for it.  Sites waiting for their first result aren't down yet.
+    pub fn is_down(&self) -> bool {
+        self.is_err() && self.status != PingStatus::Pending
+    }
+
+    /// A dual-stack site answering on one family but not the other.
+    pub fn degraded(&self) -> bool {
+        !self.is_err() && self.status_v6.is_some_and(|v6| v6 != self.status)
+    }
+
+    /// Best latency we have, IPv4 first.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.response.or(self.response_v6)
+    }
+
+    /// Latency, or why there isn't one.  Both families side by side for dual-stack sites.
+    pub fn latency_text(&self) -> String {
+        let text = |response: Option<Duration>, status: PingStatus| match response {
+            Some(resp) => format!("{resp:.2?}"),
+            None => status.label().to_string(),
+        };
+        match self.status_v6 {
+            Some(v6) => format!(
+                "{} / {}",
+                text(self.response, self.status),
+                text(self.response_v6, v6)
+            ),
+            None => text(self.response, self.status),
+        }
+    }
+
+    /// Tooltip text for the site's row or tile.
+    pub fn describe(&self) -> String {
+        if self.stale {
+            "No result for a while, the last one may be out of date".to_string()
+        } else if self.parent_down {
+            format!("{}, but so is its parent", self.error)
+        } else if self.error.is_empty() {
+            format!("Replied in {}", self.latency_text())
+        } else if self.degraded() {
+            format!("Replied in {}.  {}", self.latency_text(), self.error)
+        } else {
+            self.error.clone()
+        }
+    }
+
+    /// Folds a dual-stack site's IPv6 result into its IPv4 one.
+    pub fn merge_v6(mut self, v6: PingResponse) -> PingResponse {
+        let errors: Vec<String> = [("IPv4", &self), ("IPv6", &v6)]
+            .iter()
+            .filter(|(_, r)| r.status != PingStatus::Up)
+            .map(|(family, r)| format!("{family}: {}", r.error))
+            .collect();
+        self.error = errors.join(", ");
+        self.response_v6 = v6.response;
+        self.status_v6 = Some(v6.status);
+        self
+    }
 }
 
 /// Simple data structure for site name & ip address.
@@ -212,3 +1195,230 @@ pub struct SiteAddress {
     pub name: String,
     pub addr: IpAddr,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(name: &str, status: PingStatus, ms: u64) -> PingResponse {
+        let up = status == PingStatus::Up;
+        PingResponse {
+            name: name.to_string(),
+            response: up.then(|| Duration::from_millis(ms)),
+            status,
+            error: if up {
+                String::new()
+            } else {
+                format!("{status:?}")
+            },
+            response_v6: None,
+            status_v6: None,
+            parent_down: false,
+            stale: false,
+            round: 1,
+        }
+    }
+
+    fn dual(v4: PingStatus, v6: PingStatus) -> PingResponse {
+        result("site", v4, 10).merge_v6(result("site", v6, 20))
+    }
+
+    #[test]
+    fn dual_stack_both_up() {
+        let r = dual(PingStatus::Up, PingStatus::Up);
+        assert!(!r.is_err() && !r.degraded());
+        assert!(r.error.is_empty());
+        assert_eq!(r.rtt(), Some(Duration::from_millis(10)));
+        assert_eq!(r.response_v6, Some(Duration::from_millis(20)));
+    }
+
+    #[test]
+    fn dual_stack_one_family_down_is_degraded() {
+        let r = dual(PingStatus::Up, PingStatus::Timeout);
+        assert!(!r.is_err() && r.degraded());
+        assert_eq!(r.error, "IPv6: Timeout");
+
+        let r = dual(PingStatus::Unreachable, PingStatus::Up);
+        assert!(!r.is_err() && r.degraded());
+        assert_eq!(r.error, "IPv4: Unreachable");
+        // Falls back to the IPv6 latency, but the IPv4 one stays empty for the average.
+        assert_eq!(r.response, None);
+        assert_eq!(r.rtt(), Some(Duration::from_millis(20)));
+    }
+
+    #[test]
+    fn dual_stack_both_down() {
+        let r = dual(PingStatus::Timeout, PingStatus::Failed);
+        assert!(r.is_err() && !r.degraded());
+        assert_eq!(r.error, "IPv4: Timeout, IPv6: Failed");
+        assert_eq!(r.rtt(), None);
+    }
+
+    #[test]
+    fn single_stack() {
+        let up = result("site", PingStatus::Up, 10);
+        assert!(!up.is_err() && !up.degraded());
+        let down = result("site", PingStatus::Timeout, 10);
+        assert!(down.is_err() && down.is_down() && !down.degraded());
+        let pending = result("site", PingStatus::Pending, 10);
+        assert!(pending.is_err() && !pending.is_down());
+    }
+
+    fn sorted(mut sites: Vec<PingResponse>, mode: SortMode) -> Vec<String> {
+        let now = Local::now();
+        let last_change = BTreeMap::from([
+            ("a".to_string(), now - chrono::TimeDelta::minutes(5)),
+            ("c".to_string(), now),
+        ]);
+        sort_sites(&mut sites, mode, &last_change);
+        sites.into_iter().map(|s| s.name).collect()
+    }
+
+    fn mixed() -> Vec<PingResponse> {
+        let mut unreachable = result("b", PingStatus::Timeout, 0);
+        unreachable.parent_down = true;
+        vec![
+            result("e", PingStatus::Up, 30),
+            result("d", PingStatus::Timeout, 0),
+            unreachable,
+            result("c", PingStatus::Up, 5),
+            result("a", PingStatus::Up, 30),
+        ]
+    }
+
+    #[test]
+    fn sorts_by_name() {
+        assert_eq!(sorted(mixed(), SortMode::Name), ["a", "b", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn sorts_by_latency_timeouts_first() {
+        // Equal latencies keep name order.
+        assert_eq!(
+            sorted(mixed(), SortMode::Latency),
+            ["b", "d", "a", "e", "c"]
+        );
+    }
+
+    #[test]
+    fn sorts_by_status() {
+        assert_eq!(sorted(mixed(), SortMode::Status), ["d", "b", "a", "c", "e"]);
+    }
+
+    #[test]
+    fn sorts_by_last_change() {
+        // Sites that never changed go last, in name order.
+        assert_eq!(
+            sorted(mixed(), SortMode::LastChange),
+            ["c", "a", "b", "d", "e"]
+        );
+    }
+
+    /// Sites as they'd be read from sites.json.
+    fn configs(sites: serde_json::Value) -> BTreeMap<String, SiteConfig> {
+        serde_json::from_value::<BTreeMap<String, SiteEntry>>(sites)
+            .unwrap()
+            .into_iter()
+            .map(|(name, entry)| (name, entry.into()))
+            .collect()
+    }
+
+    /// Runs mark_unreachable and returns the sites it flagged.
+    fn unreachable(results: &[(&str, PingStatus)], config: serde_json::Value) -> Vec<String> {
+        let mut sites: Vec<PingResponse> = results
+            .iter()
+            .map(|(name, status)| result(name, *status, 10))
+            .collect();
+        mark_unreachable(&mut sites, &configs(config));
+        sites
+            .into_iter()
+            .filter(|s| s.parent_down)
+            .map(|s| s.name)
+            .collect()
+    }
+
+    #[test]
+    fn failing_child_of_failing_parent_is_unreachable() {
+        let config = serde_json::json!({
+            "router": "10.0.0.1",
+            "school": {"address": "10.0.1.1", "parent": "router"},
+            "office": {"address": "10.0.2.1", "parent": "router"},
+            "other": "10.0.3.1"
+        });
+        let flagged = unreachable(
+            &[
+                ("router", PingStatus::Timeout),
+                ("school", PingStatus::Timeout),
+                ("office", PingStatus::Up),
+                ("other", PingStatus::Timeout),
+            ],
+            config,
+        );
+        assert_eq!(flagged, ["school"]);
+    }
+
+    #[test]
+    fn parent_that_answers_means_a_real_outage() {
+        let config = serde_json::json!({
+            "core": "10.0.0.1",
+            "router": {"address": "10.0.1.1", "parent": "core"},
+            "school": {"address": "10.0.2.1", "parent": "router"}
+        });
+        let results = |router| {
+            [
+                ("core", PingStatus::Timeout),
+                ("router", router),
+                ("school", PingStatus::Timeout),
+            ]
+        };
+        // The router answers, so the school is down in its own right whatever the core is doing.
+        assert!(unreachable(&results(PingStatus::Up), config.clone()).is_empty());
+        // Behind an unreachable parent is unreachable too.
+        assert_eq!(
+            unreachable(&results(PingStatus::Timeout), config),
+            ["router", "school"]
+        );
+    }
+
+    fn check(sites: serde_json::Value) -> Result<(), String> {
+        serde_json::from_value(sites)
+            .map_err(|e| e.to_string())
+            .and_then(site_configs)
+            .map(|_| ())
+    }
+
+    #[test]
+    fn parents_must_exist() {
+        assert!(check(serde_json::json!({
+            "router": {"address": "10.0.0.1", "disabled": true},
+            "school": {"address": "10.0.1.1", "parent": "router"}
+        }))
+        .is_ok());
+        let error = check(serde_json::json!({
+            "school": {"address": "10.0.1.1", "parent": "router"}
+        }))
+        .unwrap_err();
+        assert!(error.contains("router"), "{error}");
+    }
+
+    #[test]
+    fn parent_loops_are_refused() {
+        let error = check(serde_json::json!({
+            "a": {"address": "10.0.0.1", "parent": "b"},
+            "b": {"address": "10.0.0.2", "parent": "a"}
+        }))
+        .unwrap_err();
+        assert!(error.contains("a -> b -> a"), "{error}");
+        assert!(check(serde_json::json!({
+            "e": {"address": "10.0.0.5", "parent": "e"}
+        }))
+        .is_err());
+        // Longer loops, reached from a site outside them.
+        assert!(check(serde_json::json!({
+            "a": {"address": "10.0.0.1", "parent": "b"},
+            "b": {"address": "10.0.0.2", "parent": "c"},
+            "c": {"address": "10.0.0.3", "parent": "b"}
+        }))
+        .is_err());
+    }
+}
@@ -0,0 +1,245 @@
+use super::*;
+
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Where each round of hop results gets appended, one file per day.
+pub const MTR_HISTORY_DIR: &str = "mtr_history";
+
+/// Running statistics for one hop on the way to a site.
+#[derive(Lens, Clone, PartialEq, Data)]
+pub struct MtrHop {
+    pub ttl: u8,
+    pub addr: String,
+    pub sent: u32,
+    pub lost: u32,
+    pub last: Option<Duration>,
+    pub sum: Duration,
+    pub best: Option<Duration>,
+    pub worst: Option<Duration>,
+}
+impl MtrHop {
+    pub fn new(ttl: u8, addr: String) -> Self {
+        MtrHop {
+            ttl,
+            addr,
+            sent: 0,
+            lost: 0,
+            last: None,
+            sum: Duration::ZERO,
+            best: None,
+            worst: None,
+        }
+    }
+
+    pub fn add(&mut self, response: Option<Duration>) {
+        self.sent += 1;
+        self.last = response;
+        match response {
+            Some(dur) => {
+                self.sum += dur;
+                self.best = Some(self.best.map_or(dur, |b| b.min(dur)));
+                self.worst = Some(self.worst.map_or(dur, |w| w.max(dur)));
+            }
+            None => self.lost += 1,
+        }
+    }
+
+    pub fn loss(&self) -> f64 {
+        if self.sent == 0 {
+            0.0
+        } else {
+            self.lost as f64 / self.sent as f64 * 100.0
+        }
+    }
+
+    pub fn avg(&self) -> Option<Duration> {
+        let received = self.sent - self.lost;
+        (received > 0).then(|| self.sum / received)
+    }
+}
+
+/// Hop statistics for a site being monitored mtr style.
+#[derive(Lens, Clone, PartialEq, Data)]
+pub struct MtrSite {
+    pub name: String,
+    pub hops: Vec<MtrHop>,
+}
+impl MtrSite {
+    pub fn new(name: String) -> Self {
+        MtrSite {
+            name,
+            hops: Vec::new(),
+        }
+    }
+
+    /// Folds one round of probes into the running statistics.
+    pub fn update(&mut self, round: &[TraceHop]) {
+        // Path got shorter, drop the hops that no longer exist.
+        self.hops.truncate(round.len());
+        for sample in round {
+            let index = sample.ttl as usize - 1;
+            if index >= self.hops.len() {
+                self.hops.push(MtrHop::new(sample.ttl, sample.addr.clone()));
+            }
+            let hop = &mut self.hops[index];
+            // A different router answering means the route changed, start that hop over.
+            if sample.response.is_some() && hop.addr != sample.addr {
+                *hop = MtrHop::new(sample.ttl, sample.addr.clone());
+            }
+            hop.add(sample.response);
+        }
+    }
+}
+
+/// One round of hop results as saved to the history file.
+#[derive(Serialize)]
+struct MtrRecord<'a> {
+    time: DateTime<Local>,
+    site: &'a str,
+    hops: Vec<MtrSample<'a>>,
+}
+
+#[derive(Serialize)]
+struct MtrSample<'a> {
+    ttl: u8,
+    addr: &'a str,
+    rtt_ms: Option<f64>,
+}
+
+fn mtr_day_file(date: NaiveDate) -> PathBuf {
+    Path::new(MTR_HISTORY_DIR).join(format!("{date}.jsonl"))
+}
+
+/// Appends a round to the day's history file so outages can be traced back to a hop later.
+fn save_round(site: &str, round: &[TraceHop]) -> std::io::Result<()> {
+    let time = Local::now();
+    let record = MtrRecord {
+        time,
+        site,
+        hops: round
+            .iter()
+            .map(|h| MtrSample {
+                ttl: h.ttl,
+                addr: &h.addr,
+                rtt_ms: h.response.map(|r| r.as_secs_f64() * 1000.0),
+            })
+            .collect(),
+    };
+    fs::create_dir_all(MTR_HISTORY_DIR)?;
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(mtr_day_file(time.date_naive()))?;
+    writeln!(file, "{}", serde_json::to_string(&record)?)
+}
+
+/// Probes every hop to a site once.  Sends the round back to the GUI thread and saves it to the history file.
+pub async fn mtr_round(mut cx: ContextProxy, site: SiteAddress, timeout: u64) {
+    let _ = tokio::task::spawn_blocking(move || {
+        let prober = match HopProber::new(site.addr) {
+            Ok(prober) => prober,
+            Err(e) => {
+                warn!(site = %site.name, error = %e, "Couldn't open raw socket for hop monitoring");
+                return;
+            }
+        };
+        let replies = match prober.probe_path(Duration::from_secs(timeout)) {
+            Ok(replies) => replies,
+            Err(e) => {
+                warn!(site = %site.name, error = %e, "Hop monitoring round failed");
+                return;
+            }
+        };
+        let round: Vec<TraceHop> = replies
+            .iter()
+            .zip(1..)
+            .map(|(reply, ttl)| reply.to_trace_hop(ttl))
+            .collect();
+        debug!(site = %site.name, hops = round.len(), "Hop monitoring round done");
+        if let Err(e) = save_round(&site.name, &round) {
+            warn!(error = %e, "Couldn't save hop history");
+        }
+        let _ = cx.emit(ViziaEvent::MtrRound(site.name, round));
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round(hops: &[(&str, Option<u64>)]) -> Vec<TraceHop> {
+        hops.iter()
+            .enumerate()
+            .map(|(i, (addr, ms))| TraceHop {
+                ttl: i as u8 + 1,
+                addr: addr.to_string(),
+                response: ms.map(Duration::from_millis),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keeps_running_stats_per_hop() {
+        let mut site = MtrSite::new("school".to_string());
+        site.update(&round(&[("10.0.0.1", Some(2)), ("10.1.0.1", Some(10))]));
+        site.update(&round(&[("10.0.0.1", Some(4)), ("*", None)]));
+        site.update(&round(&[("10.0.0.1", Some(3)), ("10.1.0.1", Some(20))]));
+
+        let first = &site.hops[0];
+        assert_eq!((first.ttl, first.sent, first.lost), (1, 3, 0));
+        assert_eq!(first.best, Some(Duration::from_millis(2)));
+        assert_eq!(first.worst, Some(Duration::from_millis(4)));
+        assert_eq!(first.avg(), Some(Duration::from_millis(3)));
+        assert_eq!(first.last, Some(Duration::from_millis(3)));
+
+        // A lost probe counts against the hop it was sent to, it doesn't replace its address.
+        let second = &site.hops[1];
+        assert_eq!(second.addr, "10.1.0.1");
+        assert_eq!((second.ttl, second.sent, second.lost), (2, 3, 1));
+        assert_eq!(second.avg(), Some(Duration::from_millis(15)));
+        assert!((second.loss() - 100.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn route_change_starts_the_hop_over() {
+        let mut site = MtrSite::new("school".to_string());
+        site.update(&round(&[("10.0.0.1", Some(2)), ("10.1.0.1", Some(10))]));
+        site.update(&round(&[("10.0.0.1", Some(2)), ("10.2.0.1", Some(30))]));
+        let second = &site.hops[1];
+        assert_eq!(second.addr, "10.2.0.1");
+        assert_eq!(second.sent, 1);
+        assert_eq!(second.best, Some(Duration::from_millis(30)));
+        assert_eq!(site.hops[0].sent, 2);
+    }
+
+    #[test]
+    fn path_length_follows_the_latest_round() {
+        let mut site = MtrSite::new("school".to_string());
+        site.update(&round(&[("10.0.0.1", Some(2)), ("10.1.0.1", Some(10))]));
+        site.update(&round(&[("10.0.0.1", Some(2))]));
+        assert_eq!(site.hops.len(), 1);
+
+        site.update(&round(&[
+            ("10.0.0.1", Some(2)),
+            ("10.1.0.1", Some(10)),
+            ("10.2.0.1", Some(12)),
+        ]));
+        let ttls: Vec<u8> = site.hops.iter().map(|h| h.ttl).collect();
+        assert_eq!(ttls, [1, 2, 3]);
+        assert_eq!(site.hops[1].sent, 1);
+    }
+
+    #[test]
+    fn silent_hop_on_the_first_round() {
+        let mut site = MtrSite::new("school".to_string());
+        site.update(&round(&[("*", None), ("10.1.0.1", Some(10))]));
+        assert_eq!(site.hops[0].addr, "*");
+        assert_eq!(site.hops[0].avg(), None);
+        // The router shows up once it answers.
+        site.update(&round(&[("10.0.0.1", Some(2)), ("10.1.0.1", Some(10))]));
+        assert_eq!(site.hops[0].addr, "10.0.0.1");
+        assert_eq!((site.hops[0].sent, site.hops[0].lost), (1, 0));
+    }
+}
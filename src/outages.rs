@@ -0,0 +1,101 @@
+use super::*;
+
+use chrono::TimeDelta;
+use std::io::{BufRead, BufReader, Write};
+
+/// Every status change is appended here so the log survives a restart.
+pub const EVENT_LOG_FILE: &str = "events.jsonl";
+
+/// Number of events kept in the log panel.
+pub const EVENT_LOG_LEN: usize = 500;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum OutageKind {
+    Down,
+    Unreachable, // Failing, but so is its parent.
+    Recovered,
+}
+
+/// A site changing state, as saved to the event log file.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct OutageEvent {
+    pub time: DateTime<Local>,
+    pub site: String,
+    pub kind: OutageKind,
+    pub down_secs: Option<i64>, // How long it was down for, on recovery.
+}
+impl OutageEvent {
+    /// e.g. "Lincoln Elem router DOWN at 10:32:05 AM".
+    pub fn describe(&self) -> String {
+        let time = self.time.format("%m/%d %r");
+        match self.kind {
+            OutageKind::Down => format!("{time}  {} DOWN", self.site),
+            OutageKind::Unreachable => format!("{time}  {} UNREACHABLE (parent down)", self.site),
+            OutageKind::Recovered => match self.down_secs {
+                Some(secs) => format!(
+                    "{time}  {} recovered after {}",
+                    self.site,
+                    format_duration(TimeDelta::seconds(secs))
+                ),
+                None => format!("{time}  {} recovered", self.site),
+            },
+        }
+    }
+}
+
+/// A row in the event log panel.
+#[derive(Lens, Clone, PartialEq, Data)]
+pub struct LogEntry {
+    pub site: String,
+    pub text: String,
+    pub is_down: bool,
+}
+impl From<&OutageEvent> for LogEntry {
+    fn from(event: &OutageEvent) -> Self {
+        LogEntry {
+            site: event.site.clone(),
+            text: event.describe(),
+            is_down: event.kind != OutageKind::Recovered,
+        }
+    }
+}
+
+pub fn append_event(event: &OutageEvent) -> std::io::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(EVENT_LOG_FILE)?;
+    writeln!(file, "{}", serde_json::to_string(event)?)
+}
+
+/// Every saved event, oldest first.  Unreadable lines are skipped.
+pub fn load_events() -> Vec<OutageEvent> {
+    let Ok(file) = fs::File::open(EVENT_LOG_FILE) else {
+        return Vec::new();
+    };
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str(&line).ok())
+        .collect()
+}
+
+/// Writes the whole event log out as CSV.
+pub fn export_events(path: &Path) -> Result<(), String> {
+    let mut writer = csv::Writer::from_path(path).map_err(|e| e.to_string())?;
+    for event in load_events() {
+        writer.serialize(&event).map_err(|e| e.to_string())?;
+    }
+    writer.flush().map_err(|e| e.to_string())
+}
+
+/// The newest events for the log panel, newest first.
+pub fn recent_log() -> Vec<LogEntry> {
+    load_events()
+        .iter()
+        .rev()
+        .take(EVENT_LOG_LEN)
+        .map(LogEntry::from)
+        .collect()
+}
@@ -0,0 +1,193 @@
+use super::*;
+
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use tokio::sync::broadcast;
+
+/// Every ping result is kept here, one file per day.
+pub const HISTORY_DIR: &str = "history";
+
+/// A ping result as saved to the history files.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ProbeRecord {
+    pub time: DateTime<Local>,
+    pub site: String,
+    pub rtt_ms: Option<f64>, // None if the probe failed.
+}
+impl ProbeRecord {
+    pub fn new(response: &PingResponse, time: DateTime<Local>) -> Self {
+        ProbeRecord {
+            time,
+            site: response.name.clone(),
+            rtt_ms: response.rtt().map(|r| r.as_secs_f64() * 1000.0),
+        }
+    }
+}
+
+fn day_file(date: NaiveDate) -> PathBuf {
+    Path::new(HISTORY_DIR).join(format!("{date}.jsonl"))
+}
+
+/// Appends records to the history files.  Keeps the day's file open between results.
+#[derive(Default)]
+pub struct RecordWriter {
+    date: Option<NaiveDate>,
+    file: Option<BufWriter<fs::File>>,
+}
+impl RecordWriter {
+    pub fn write(&mut self, records: &[ProbeRecord]) -> std::io::Result<()> {
+        let result = self.append(records);
+        if result.is_err() {
+            // Start over with a fresh file next time.
+            self.file = None;
+        }
+        result
+    }
+
+    fn append(&mut self, records: &[ProbeRecord]) -> std::io::Result<()> {
+        for record in records {
+            let date = record.time.date_naive();
+            if self.file.is_none() || self.date != Some(date) {
+                // New day, new file.
+                if let Some(mut old) = self.file.take() {
+                    old.flush()?;
+                }
+                fs::create_dir_all(HISTORY_DIR)?;
+                let file = fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(day_file(date))?;
+                self.file = Some(BufWriter::new(file));
+                self.date = Some(date);
+            }
+            if let Some(file) = &mut self.file {
+                writeln!(file, "{}", serde_json::to_string(record)?)?;
+            }
+        }
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Longest a single record is taken to stand for.  Bigger gaps mean the monitor wasn't running,
+/// and that time doesn't count as up or down.
+pub const MAX_RECORD_SPAN: f64 = 300.0;
+
+/// Seconds of time each record stands for: up to the site's next record.  Failing sites are probed
+/// more often, so counting records instead of time would make outages look far longer than they were.
+/// A site's last record stands for as long as the one before it.  Records must be oldest first.
+pub fn record_spans(records: &[ProbeRecord]) -> Vec<f64> {
+    let mut spans = vec![0.0; records.len()];
+    // Each site's latest record so far, and the gap before it.
+    let mut latest: BTreeMap<&str, (usize, f64)> = BTreeMap::new();
+    for (i, record) in records.iter().enumerate() {
+        let gap = match latest.get(record.site.as_str()) {
+            Some(&(prev, _)) => {
+                let gap = (record.time - records[prev].time).num_milliseconds() as f64 / 1000.0;
+                spans[prev] = gap.clamp(0.0, MAX_RECORD_SPAN);
+                spans[prev]
+            }
+            None => 1.0,
+        };
+        latest.insert(record.site.as_str(), (i, gap));
+    }
+    // Nothing follows the last records, carry the gap before them over.
+    for (i, gap) in latest.into_values() {
+        spans[i] = gap;
+    }
+    spans
+}
+
+/// Every record from `from` through `to`, oldest first.  Missing days and unreadable lines are skipped.
+pub fn load_records(from: NaiveDate, to: NaiveDate) -> Vec<ProbeRecord> {
+    let mut records = Vec::new();
+    for date in from.iter_days().take_while(|d| *d <= to) {
+        let Ok(file) = fs::File::open(day_file(date)) else {
+            continue;
+        };
+        records.extend(
+            BufReader::new(file)
+                .lines()
+                .map_while(Result::ok)
+                .filter_map(|line| serde_json::from_str::<ProbeRecord>(&line).ok()),
+        );
+    }
+    records
+}
+
+/// Saves every ping result to the history files.  Writes happen on the blocking pool, a batch at a time.
+pub async fn record_probes(mut rx: broadcast::Receiver<PingResponse>) {
+    let mut writer = RecordWriter::default();
+    loop {
+        let mut batch = match rx.recv().await {
+            Ok(response) => vec![ProbeRecord::new(&response, Local::now())],
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                warn!(
+                    skipped = n,
+                    "History recorder fell behind, results weren't saved"
+                );
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        // Whatever else came in meanwhile goes in the same write.
+        while let Ok(response) = rx.try_recv() {
+            batch.push(ProbeRecord::new(&response, Local::now()));
+        }
+        writer = match tokio::task::spawn_blocking(move || {
+            let result = writer.write(&batch);
+            (writer, result)
+        })
+        .await
+        {
+            Ok((writer, result)) => {
+                if let Err(e) = result {
+                    warn!(error = %e, "Couldn't save probe history");
+                }
+                writer
+            }
+            Err(e) => {
+                error!(error = %e, "Probe history writer panicked");
+                RecordWriter::default()
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeDelta, TimeZone};
+
+    fn record(site: &str, secs: i64, up: bool) -> ProbeRecord {
+        ProbeRecord {
+            time: Local.with_ymd_and_hms(2024, 5, 6, 10, 0, 0).unwrap() + TimeDelta::seconds(secs),
+            site: site.to_string(),
+            rtt_ms: up.then_some(5.0),
+        }
+    }
+
+    #[test]
+    fn spans_run_to_the_sites_next_record() {
+        let records = [
+            record("a", 0, true),
+            record("b", 5, true),
+            record("a", 10, false),
+            record("b", 35, true),
+            record("a", 40, true),
+        ];
+        // The last record of each site lasts as long as the gap before it.
+        assert_eq!(record_spans(&records), [10.0, 30.0, 30.0, 30.0, 30.0]);
+    }
+
+    #[test]
+    fn gaps_are_capped() {
+        let records = [record("a", 0, true), record("a", 3600, true)];
+        assert_eq!(record_spans(&records), [MAX_RECORD_SPAN, MAX_RECORD_SPAN]);
+        // Nothing to go on for a lone record.
+        assert_eq!(record_spans(&records[..1]), [1.0]);
+        assert!(record_spans(&[]).is_empty());
+    }
+}
@@ -0,0 +1,485 @@
+use super::*;
+
+use chrono::{Datelike, TimeDelta};
+use std::fmt::Write;
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum Period {
+    Daily,
+    Weekly,
+    Monthly,
+}
+impl Period {
+    /// First day of the period `date` falls in.  Weeks start on Monday.
+    pub fn start(&self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Daily => date,
+            Period::Weekly => date - TimeDelta::days(date.weekday().num_days_from_monday() as i64),
+            Period::Monthly => date.with_day(1).unwrap(),
+        }
+    }
+
+    pub fn label(&self, start: NaiveDate) -> String {
+        match self {
+            Period::Daily => start.format("%A %Y-%m-%d").to_string(),
+            Period::Weekly => format!("Week of {start}"),
+            Period::Monthly => start.format("%B %Y").to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum ReportFormat {
+    Html,
+    Markdown,
+}
+
+/// Planned downtime from settings.json.  Probes during a window don't count against availability.
+#[derive(Deserialize, Clone)]
+pub struct MaintenanceWindow {
+    pub start: DateTime<Local>,
+    pub end: DateTime<Local>,
+    #[serde(default)]
+    pub sites: Vec<String>, // Sites covered by the window.  Empty with no groups means every site.
+    #[serde(default)]
+    pub groups: Vec<String>, // Groups covered by the window.
+}
+impl MaintenanceWindow {
+    pub fn covers(&self, site: &str, group: Option<&str>, time: DateTime<Local>) -> bool {
+        time >= self.start && time < self.end && self.applies_to(site, group)
+    }
+
+    /// Whether the window is for this site, whenever it is.
+    pub fn applies_to(&self, site: &str, group: Option<&str>) -> bool {
+        let everything = self.sites.is_empty() && self.groups.is_empty();
+        everything
+            || self.sites.iter().any(|s| s == site)
+            || group.is_some_and(|g| self.groups.iter().any(|s| s == g))
+    }
+}
+
+/// How much of the time between two of a site's records counts towards an outage.  Maintenance
+/// windows don't count, and like record_spans, a gap where the monitor wasn't running counts for
+/// no more than MAX_RECORD_SPAN.
+fn outage_time(
+    from: DateTime<Local>,
+    to: DateTime<Local>,
+    site: &str,
+    group: Option<&str>,
+    maintenance: &[MaintenanceWindow],
+) -> TimeDelta {
+    let mut time = to - from;
+    for window in maintenance.iter().filter(|w| w.applies_to(site, group)) {
+        let overlap = window.end.min(to) - window.start.max(from);
+        if overlap > TimeDelta::zero() {
+            time -= overlap;
+        }
+    }
+    let max = TimeDelta::milliseconds((MAX_RECORD_SPAN * 1000.0) as i64);
+    time.clamp(TimeDelta::zero(), max)
+}
+
+/// Availability figures for a site or group over one period.
+#[derive(Default, Clone)]
+pub struct Availability {
+    pub probes: u64,
+    pub failed: u64,
+    pub up_secs: f64,   // Time covered by probes that were answered.
+    pub down_secs: f64, // Time covered by probes that failed.
+    pub outages: Vec<TimeDelta>,
+}
+impl Availability {
+    /// Share of the time the site was up.  Weighted by time so the quicker pings during an outage don't count extra.
+    pub fn percent(&self) -> Option<f64> {
+        let total = self.up_secs + self.down_secs;
+        (total > 0.0).then(|| self.up_secs / total * 100.0)
+    }
+
+    /// Mean time to recovery.
+    pub fn mttr(&self) -> Option<TimeDelta> {
+        let count = self.outages.len() as i32;
+        (count > 0).then(|| self.outages.iter().sum::<TimeDelta>() / count)
+    }
+
+    pub fn longest(&self) -> Option<TimeDelta> {
+        self.outages.iter().max().copied()
+    }
+
+    fn merge(&mut self, other: &Availability) {
+        self.probes += other.probes;
+        self.failed += other.failed;
+        self.up_secs += other.up_secs;
+        self.down_secs += other.down_secs;
+        self.outages.extend(other.outages.iter().copied());
+    }
+}
+
+/// An outage that hasn't ended yet, while the figures are being worked out.
+struct OpenOutage {
+    period: NaiveDate, // The period it started in, which it's counted under.
+    length: TimeDelta, // Up to the last failed probe, less maintenance & gaps.
+    last_failed: DateTime<Local>,
+}
+
+/// Report section for one day, week or month.
+pub struct PeriodReport {
+    pub label: String,
+    pub groups: Vec<(String, Availability)>,
+    pub sites: Vec<(String, Option<String>, Availability)>,
+}
+
+/// Works out availability, outages and recovery times per period.  Records must be oldest first.
+pub fn build_report(
+    records: &[ProbeRecord],
+    sites: &BTreeMap<String, SiteConfig>,
+    maintenance: &[MaintenanceWindow],
+    period: Period,
+) -> Vec<PeriodReport> {
+    let mut periods: BTreeMap<NaiveDate, BTreeMap<String, Availability>> = BTreeMap::new();
+    let mut open: BTreeMap<String, OpenOutage> = BTreeMap::new();
+    let mut ended: Vec<(NaiveDate, String, TimeDelta)> = Vec::new();
+    for (record, span) in records.iter().zip(record_spans(records)) {
+        let group = sites.get(&record.site).and_then(|c| c.group.as_deref());
+        if maintenance
+            .iter()
+            .any(|w| w.covers(&record.site, group, record.time))
+        {
+            continue;
+        }
+        let start = period.start(record.time.date_naive());
+        let availability = periods
+            .entry(start)
+            .or_default()
+            .entry(record.site.clone())
+            .or_default();
+        availability.probes += 1;
+        match record.rtt_ms {
+            Some(_) => availability.up_secs += span,
+            None => availability.down_secs += span,
+        }
+        let since_last = |outage: &OpenOutage| {
+            outage_time(
+                outage.last_failed,
+                record.time,
+                &record.site,
+                group,
+                maintenance,
+            )
+        };
+        match (record.rtt_ms, open.get_mut(&record.site)) {
+            (None, None) => {
+                availability.failed += 1;
+                open.insert(
+                    record.site.clone(),
+                    OpenOutage {
+                        period: start,
+                        length: TimeDelta::zero(),
+                        last_failed: record.time,
+                    },
+                );
+            }
+            (None, Some(outage)) => {
+                availability.failed += 1;
+                let time = since_last(outage);
+                outage.length += time;
+                outage.last_failed = record.time;
+            }
+            (Some(_), Some(_)) => {
+                if let Some(outage) = open.remove(&record.site) {
+                    let length = outage.length + since_last(&outage);
+                    ended.push((outage.period, record.site.clone(), length));
+                }
+            }
+            (Some(_), None) => {}
+        }
+    }
+    // Still down when the records end, count it up to the last probe.
+    for (site, outage) in open {
+        ended.push((outage.period, site, outage.length));
+    }
+    // Each outage counts once, in full, in the period it started in.  One that runs past midnight
+    // or the end of the month isn't split in two.
+    for (start, site, duration) in ended {
+        if let Some(availability) = periods.get_mut(&start).and_then(|p| p.get_mut(&site)) {
+            availability.outages.push(duration);
+        }
+    }
+
+    periods
+        .into_iter()
+        .map(|(start, tallies)| {
+            let mut groups: BTreeMap<String, Availability> = BTreeMap::new();
+            let mut site_rows = Vec::new();
+            for (site, availability) in tallies {
+                let group = sites.get(&site).and_then(|c| c.group.clone());
+                if let Some(name) = &group {
+                    groups.entry(name.clone()).or_default().merge(&availability);
+                }
+                site_rows.push((site, group, availability));
+            }
+            PeriodReport {
+                label: period.label(start),
+                groups: groups.into_iter().collect(),
+                sites: site_rows,
+            }
+        })
+        .collect()
+}
+
+/// e.g. "4m12s".
+pub fn format_duration(duration: TimeDelta) -> String {
+    let secs = duration.num_seconds().max(0);
+    match (secs / 3600, secs % 3600 / 60, secs % 60) {
+        (0, 0, s) => format!("{s}s"),
+        (0, m, s) => format!("{m}m{s:02}s"),
+        (h, m, s) => format!("{h}h{m:02}m{s:02}s"),
+    }
+}
+
+/// Cells for a report row: availability, outages, MTTR, longest outage.
+fn cells(a: &Availability) -> [String; 4] {
+    [
+        a.percent().map_or("-".to_string(), |p| format!("{p:.3}%")),
+        a.outages.len().to_string(),
+        a.mttr().map_or("-".to_string(), format_duration),
+        a.longest().map_or("-".to_string(), format_duration),
+    ]
+}
+
+pub fn render_markdown(title: &str, report: &[PeriodReport]) -> String {
+    let mut out = format!("# {title}\n");
+    for period in report {
+        let _ = writeln!(out, "\n## {}\n", period.label);
+        if !period.groups.is_empty() {
+            out.push_str("| Group | Availability | Outages | MTTR | Longest |\n");
+            out.push_str("|---|---|---|---|---|\n");
+            for (name, a) in &period.groups {
+                let _ = writeln!(out, "| {name} | {} |", cells(a).join(" | "));
+            }
+            out.push('\n');
+        }
+        out.push_str("| Site | Group | Availability | Outages | MTTR | Longest |\n");
+        out.push_str("|---|---|---|---|---|---|\n");
+        for (name, group, a) in &period.sites {
+            let group = group.as_deref().unwrap_or("");
+            let _ = writeln!(out, "| {name} | {group} | {} |", cells(a).join(" | "));
+        }
+    }
+    out
+}
+
+pub fn render_html(title: &str, report: &[PeriodReport]) -> String {
+    let esc = |s: &str| {
+        s.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    };
+    let row = |tag: &str, values: &[String]| {
+        let cells: String = values
+            .iter()
+            .map(|v| format!("<{tag}>{}</{tag}>", esc(v)))
+            .collect();
+        format!("<tr>{cells}</tr>\n")
+    };
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n\
+         <style>body {{ font-family: sans-serif; }} table {{ border-collapse: collapse; margin-bottom: 20px; }} \
+         td, th {{ border: 1px solid gray; padding: 4px 12px; text-align: left; }}</style>\n\
+         </head>\n<body>\n<h1>{0}</h1>\n",
+        esc(title)
+    );
+    for period in report {
+        let _ = writeln!(out, "<h2>{}</h2>", esc(&period.label));
+        if !period.groups.is_empty() {
+            out.push_str("<table>\n");
+            out.push_str(&row(
+                "th",
+                &["Group", "Availability", "Outages", "MTTR", "Longest"].map(String::from),
+            ));
+            for (name, a) in &period.groups {
+                let mut values = vec![name.clone()];
+                values.extend(cells(a));
+                out.push_str(&row("td", &values));
+            }
+            out.push_str("</table>\n");
+        }
+        out.push_str("<table>\n");
+        out.push_str(&row(
+            "th",
+            &[
+                "Site",
+                "Group",
+                "Availability",
+                "Outages",
+                "MTTR",
+                "Longest",
+            ]
+            .map(String::from),
+        ));
+        for (name, group, a) in &period.sites {
+            let mut values = vec![name.clone(), group.clone().unwrap_or_default()];
+            values.extend(cells(a));
+            out.push_str(&row("td", &values));
+        }
+        out.push_str("</table>\n");
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// Builds a report for `from` through `to` from the history files and writes it out.  Returns the file written.
+pub fn write_report(
+    from: NaiveDate,
+    to: NaiveDate,
+    period: Period,
+    format: ReportFormat,
+    out: Option<String>,
+) -> Result<PathBuf, String> {
+    if to < from {
+        return Err("End date is before start date".to_string());
+    }
+    let sites = load_sites()?;
+    let settings = read_settings();
+    let records = load_records(from, to);
+    let report = build_report(&records, &sites, &settings.maintenance, period);
+
+    let title = format!("Site Availability {from} to {to}");
+    let (data, extension) = match format {
+        ReportFormat::Html => (render_html(&title, &report), "html"),
+        ReportFormat::Markdown => (render_markdown(&title, &report), "md"),
+    };
+    let file = PathBuf::from(out.unwrap_or_else(|| format!("report-{from}-{to}.{extension}")));
+    fs::write(&file, data).map_err(|e| e.to_string())?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(month: u32, day: u32, hour: u32, min: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2024, month, day, hour, min, 0)
+            .unwrap()
+    }
+
+    /// A probe a minute for a site, `up` for each one.
+    fn probes(site: &str, start: DateTime<Local>, up: &[bool]) -> Vec<ProbeRecord> {
+        up.iter()
+            .enumerate()
+            .map(|(i, up)| ProbeRecord {
+                time: start + TimeDelta::minutes(i as i64),
+                site: site.to_string(),
+                rtt_ms: up.then_some(5.0),
+            })
+            .collect()
+    }
+
+    /// `down` failed probes between two answered ones.
+    fn outage(down: usize) -> Vec<bool> {
+        let mut up = vec![true];
+        up.extend(vec![false; down]);
+        up.push(true);
+        up
+    }
+
+    fn sites(sites: serde_json::Value) -> BTreeMap<String, SiteConfig> {
+        serde_json::from_value(sites).unwrap()
+    }
+
+    fn site<'a>(period: &'a PeriodReport, name: &str) -> &'a Availability {
+        &period.sites.iter().find(|(n, _, _)| n == name).unwrap().2
+    }
+
+    #[test]
+    fn outage_counts_in_the_period_it_started() {
+        // Down from 23:55 on the 30th to 00:10 on the 1st.
+        let records = probes("a", at(4, 30, 23, 54), &outage(15));
+        let config = sites(serde_json::json!({"a": {"address": "10.0.0.1"}}));
+        let report = build_report(&records, &config, &[], Period::Monthly);
+        assert_eq!(report.len(), 2);
+
+        let april = site(&report[0], "a");
+        assert_eq!(april.outages, [TimeDelta::minutes(15)]);
+        assert_eq!(april.failed, 5);
+        let may = site(&report[1], "a");
+        assert!(may.outages.is_empty());
+        assert_eq!(may.failed, 10);
+        assert_eq!(may.mttr(), None);
+    }
+
+    #[test]
+    fn maintenance_is_left_out_of_outages() {
+        // Down 10:00 to 10:30, with maintenance from 10:10 to 10:20.
+        let records = probes("a", at(5, 6, 9, 59), &outage(30));
+        let config = sites(serde_json::json!({"a": {"address": "10.0.0.1", "group": "G"}}));
+        let window = MaintenanceWindow {
+            start: at(5, 6, 10, 10),
+            end: at(5, 6, 10, 20),
+            sites: Vec::new(),
+            groups: vec!["G".to_string()],
+        };
+        let report = build_report(&records, &config, &[window.clone()], Period::Daily);
+        let a = site(&report[0], "a");
+        assert_eq!(a.outages, [TimeDelta::minutes(20)]);
+        assert_eq!(a.failed, 20);
+
+        // A window for another site changes nothing.
+        let other = MaintenanceWindow {
+            sites: vec!["b".to_string()],
+            groups: Vec::new(),
+            ..window
+        };
+        let report = build_report(&records, &config, &[other], Period::Daily);
+        assert_eq!(site(&report[0], "a").outages, [TimeDelta::minutes(30)]);
+    }
+
+    #[test]
+    fn gaps_in_the_records_are_capped() {
+        // Down at 10:00 & 10:01, then the monitor was off until 12:00.  Still down, back at 12:01.
+        let mut records = probes("a", at(5, 6, 10, 0), &[false, false]);
+        records.extend(probes("a", at(5, 6, 12, 0), &[false, true]));
+        let config = sites(serde_json::json!({"a": {"address": "10.0.0.1"}}));
+        let report = build_report(&records, &config, &[], Period::Daily);
+        let max = TimeDelta::seconds(MAX_RECORD_SPAN as i64);
+        assert_eq!(site(&report[0], "a").outages, [TimeDelta::minutes(2) + max]);
+    }
+
+    #[test]
+    fn groups_merge_their_sites() {
+        let mut records = probes("a", at(5, 6, 10, 0), &[true; 10]);
+        records.extend(probes("b", at(5, 6, 10, 0), &outage(3)));
+        records.extend(probes("c", at(5, 6, 10, 0), &outage(1)));
+        records.sort_by_key(|r| r.time);
+        let config = sites(serde_json::json!({
+            "a": {"address": "10.0.0.1", "group": "G"},
+            "b": {"address": "10.0.0.2", "group": "G"},
+            "c": {"address": "10.0.0.3"}
+        }));
+        let report = build_report(&records, &config, &[], Period::Daily);
+        let period = &report[0];
+        assert_eq!(period.sites.len(), 3);
+        // The ungrouped site isn't in any group.
+        assert_eq!(period.groups.len(), 1);
+        let (name, group) = &period.groups[0];
+        assert_eq!(name, "G");
+        assert_eq!((group.probes, group.failed), (15, 3));
+        assert_eq!(group.outages, [TimeDelta::minutes(3)]);
+        // 10 minutes up from a, 2 of 5 from b.  Each last record counts as long as the one before it.
+        assert_eq!(group.up_secs, 12.0 * 60.0);
+        assert_eq!(group.down_secs, 3.0 * 60.0);
+        assert_eq!(group.percent(), Some(80.0));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(format_duration(TimeDelta::seconds(42)), "42s");
+        assert_eq!(format_duration(TimeDelta::seconds(252)), "4m12s");
+        assert_eq!(
+            format_duration(TimeDelta::seconds(3 * 3600 + 65)),
+            "3h01m05s"
+        );
+    }
+}
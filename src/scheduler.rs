@@ -0,0 +1,688 @@
+use super::*;
+
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, mpsc::UnboundedReceiver, Semaphore};
+use tokio::time::{sleep, sleep_until, Instant};
+
+/// Seconds between probes for sites that don't set their own interval.  The GUI's refresh interval replaces it.
+pub const DEFAULT_INTERVAL: u64 = 30;
+
+/// Changes passed from the tokio main loop to the scheduler.
+pub enum ScheduleEvent {
+    Sites(BTreeMap<String, SiteConfig>),
+    Interval(u64),
+    Timeout(u64),
+    Payload(Payload),
+    ProbeNow, // Probe every site once, on top of the schedule.
+}
+
+/// Everything needed to send probes out and get the results back.
+#[derive(Clone)]
+pub struct Prober {
+    pub cx: ContextProxy,
+    pub results: broadcast::Sender<PingResponse>,
+    socket: SocketOptions, // Options from settings.json, for sites that don't set their own.
+    // Clients by family (true for IPv6) & socket options.  Errors are kept for the affected sites' tooltips,
+    // and tried again when sites.json is reloaded.
+    clients: Arc<Mutex<BTreeMap<(bool, SocketOptions), Result<Client, ClientError>>>>,
+    limit: Arc<Semaphore>, // Caps how many pings are out at once.
+    in_flight: Arc<Mutex<BTreeSet<String>>>, // Sites with a ping out, or waiting on the limit.
+    skipped: Arc<AtomicU64>, // Pings skipped because the last one hadn't finished.
+    rounds: Arc<AtomicU64>, // Last round number handed out.
+    valid_from: Arc<Mutex<BTreeMap<String, u64>>>, // Rounds up to this were for a site's old address.
+}
+impl Prober {
+    pub fn new(
+        cx: ContextProxy,
+        results: broadcast::Sender<PingResponse>,
+        socket: SocketOptions,
+        client_v4: Result<Client, ClientError>,
+        client_v6: Result<Client, ClientError>,
+        max_probes: usize,
+    ) -> Self {
+        let clients = BTreeMap::from([
+            ((false, socket.clone()), client_v4),
+            ((true, socket.clone()), client_v6),
+        ]);
+        Prober {
+            cx,
+            results,
+            socket,
+            clients: Arc::new(Mutex::new(clients)),
+            limit: Arc::new(Semaphore::new(max_probes.max(1))),
+            in_flight: Arc::new(Mutex::new(BTreeSet::new())),
+            skipped: Arc::new(AtomicU64::new(0)),
+            rounds: Arc::new(AtomicU64::new(0)),
+            valid_from: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// The client for a site's address family & socket options.  Opened the first time a site needs it.
+    fn client(&self, addr: IpAddr, config: &SiteConfig) -> Result<Client, ClientError> {
+        let options = config.socket.or(&self.socket);
+        let v6 = addr.is_ipv6();
+        self.clients
+            .lock()
+            .unwrap()
+            .entry((v6, options))
+            .or_insert_with_key(|(v6, options)| {
+                let (kind, family) = if *v6 {
+                    (ICMP::V6, "IPv6")
+                } else {
+                    (ICMP::V4, "IPv4")
+                };
+                open_client(kind, family, options).map_err(|e| ClientError::new(family, &e))
+            })
+            .clone()
+    }
+
+    /// Results still out for sites that were removed or readdressed are thrown away when they come back.
+    /// Clients no site needs any more are closed, and ones that couldn't be opened get another try.
+    pub fn set_sites(
+        &self,
+        old: &BTreeMap<String, SiteConfig>,
+        new: &BTreeMap<String, SiteConfig>,
+    ) {
+        let round = self.rounds.load(Ordering::Relaxed);
+        let mut valid_from = self.valid_from.lock().unwrap();
+        for (name, config) in old {
+            let addresses = |c: &SiteConfig| (c.address, c.address_v6);
+            if new.get(name).map(addresses) != Some(addresses(config)) {
+                valid_from.insert(name.clone(), round);
+            }
+        }
+
+        let used: BTreeSet<(bool, SocketOptions)> = new
+            .values()
+            .flat_map(|config| {
+                let options = config.socket.or(&self.socket);
+                [Some(config.address), config.address_v6]
+                    .into_iter()
+                    .flatten()
+                    .map(move |addr| (addr.is_ipv6(), options.clone()))
+            })
+            .collect();
+        let mut clients = self.clients.lock().unwrap();
+        let before = clients.len();
+        clients.retain(|key, client| client.is_ok() && used.contains(key));
+        if clients.len() != before {
+            debug!(
+                closed = before - clients.len(),
+                "Dropped ping clients that failed or aren't used"
+            );
+        }
+    }
+
+    /// Pings a site after `delay`, and probes every hop to it too if `hops` is set.  Both count as one
+    /// probe against the limit, and the site stays in flight until both are done.  
+    /// Skipped if the site's last probe is still out, so a short interval can't pile them up.  
+    pub fn probe(
+        &self,
+        name: &str,
+        config: &SiteConfig,
+        timeout: u64,
+        payload: Payload,
+        delay: Duration,
+        hops: bool,
+    ) {
+        if !self.in_flight.lock().unwrap().insert(name.to_string()) {
+            debug!(site = %name, "Last probe still out, skipping");
+            self.skipped.fetch_add(1, Ordering::Relaxed);
+            self.report();
+            return;
+        }
+        let round = self.rounds.fetch_add(1, Ordering::Relaxed) + 1;
+
+        let site = SiteAddress {
+            name: name.to_string(),
+            addr: config.address,
+        };
+        let hop_round = hops.then(|| {
+            let hops = SiteAddress {
+                name: name.to_string(),
+                addr: config.address,
+            };
+            mtr_round(self.cx.clone(), hops, timeout)
+        });
+        // Check address type & options and send the appropriate client to the task
+        let client = self.client(config.address, config);
+        // Dual-stack sites get their IPv6 address pinged at the same time.
+        let second = config.address_v6.map(|addr| {
+            let site = SiteAddress {
+                name: name.to_string(),
+                addr,
+            };
+            (self.client(addr, config), site)
+        });
+        let prober = self.clone();
+        tokio::spawn(async move {
+            let _done = InFlightGuard {
+                prober: prober.clone(),
+                name: site.name.clone(),
+            };
+            sleep(delay).await;
+            let Ok(_permit) = prober.limit.acquire().await else {
+                return;
+            };
+            let pings = async {
+                let first = ping_or_report(client, site, round, timeout, payload.to_bytes());
+                let response = match second {
+                    Some((client, site)) => {
+                        let second =
+                            ping_or_report(client, site, round, timeout, payload.to_bytes());
+                        let (v4, v6) = tokio::join!(first, second);
+                        v4.merge_v6(v6)
+                    }
+                    None => first.await,
+                };
+                let valid_from = prober
+                    .valid_from
+                    .lock()
+                    .unwrap()
+                    .get(&response.name)
+                    .copied()
+                    .unwrap_or(0);
+                if round <= valid_from {
+                    debug!(site = %response.name, round, "Discarding result for the site's old address");
+                    return;
+                }
+                // Send it out to whoever's listening.
+                if prober.results.send(response).is_err() {
+                    warn!("Nothing is listening for ping results");
+                }
+            };
+            // The ping result goes out as soon as it's in, the hop round can take the whole timeout.
+            match hop_round {
+                Some(hop_round) => {
+                    tokio::join!(pings, hop_round);
+                }
+                None => pings.await,
+            }
+        });
+    }
+
+    /// Sends the in-flight & skipped counts to the GUI.
+    fn report(&self) {
+        let in_flight = self.in_flight.lock().unwrap().len();
+        let skipped = self.skipped.load(Ordering::Relaxed);
+        let _ = self
+            .cx
+            .clone()
+            .emit(ViziaEvent::ProbeStats(in_flight, skipped));
+    }
+}
+
+/// Pings with the client if it could be opened, otherwise reports why it couldn't.
+async fn ping_or_report(
+    client: Result<Client, ClientError>,
+    site: SiteAddress,
+    round: u64,
+    timeout: u64,
+    payload: Vec<u8>,
+) -> PingResponse {
+    match client {
+        Ok(client) => ping(client, site, round, timeout, payload, true).await,
+        Err(error) => unpingable(site, round, error),
+    }
+}
+
+/// Takes a site off the in-flight list when its ping task ends, even if it panicked.
+struct InFlightGuard {
+    prober: Prober,
+    name: String,
+}
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        // Don't panic again inside a panic, the lock may be poisoned.
+        if let Ok(mut in_flight) = self.prober.in_flight.lock() {
+            in_flight.remove(&self.name);
+        }
+        self.prober.report();
+    }
+}
+
+/// A probe to send out, `delay` after the first one of the batch.
+struct DueProbe {
+    name: String,
+    delay: Duration,
+    hops: bool, // Probe every hop too.
+}
+
+/// When each site is next due, and the settings its probes go out with.
+struct Schedule {
+    sites: BTreeMap<String, SiteConfig>,
+    interval: u64,
+    timeout: u64,
+    payload: Payload,
+    due: BTreeMap<String, Instant>,
+    outage_interval: u64,
+    stable_after: u32,
+    spacing: Duration, // Gap between pings that are due at the same time.
+    failing: BTreeMap<String, u32>, // Sites on the outage interval, with how many replies they've had in a row.
+    hops_due: BTreeMap<String, Instant>, // When mtr sites are next due a hop round.
+}
+impl Schedule {
+    fn interval_of(&self, name: &str, config: &SiteConfig) -> Duration {
+        let mut interval = config.interval.unwrap_or(self.interval);
+        if self.failing.contains_key(name) {
+            interval = interval.min(self.outage_interval);
+        }
+        Duration::from_secs(interval.max(1))
+    }
+
+    /// Switches failing sites to the outage interval to confirm the outage & catch the recovery,
+    /// and back again once they've replied enough times in a row.
+    fn record(&mut self, response: &PingResponse) {
+        let Some(config) = self.sites.get(&response.name) else {
+            return;
+        };
+        if response.is_err() {
+            if self.failing.insert(response.name.clone(), 0).is_none() {
+                debug!(site = %response.name, "Site failing, probing on the outage interval");
+                let next = Instant::now() + self.interval_of(&response.name, config);
+                if let Some(due) = self.due.get_mut(&response.name) {
+                    *due = (*due).min(next);
+                }
+            }
+        } else if let Some(replies) = self.failing.get_mut(&response.name) {
+            *replies += 1;
+            if *replies >= self.stable_after {
+                self.failing.remove(&response.name);
+                debug!(site = %response.name, "Site stable, back to its normal interval");
+            }
+        }
+    }
+
+    /// Keeps each site's place in the schedule across changes.  New sites start at a random point in their
+    /// first interval so they don't all go out together, and shortened intervals take effect right away.
+    fn reschedule(&mut self) {
+        let now = Instant::now();
+        let sites = &self.sites;
+        self.due.retain(|name, _| sites.contains_key(name));
+        self.failing.retain(|name, _| sites.contains_key(name));
+        self.hops_due.retain(|name, _| sites.contains_key(name));
+        for (name, config) in &self.sites {
+            let interval = self.interval_of(name, config);
+            let due = self
+                .due
+                .entry(name.clone())
+                .or_insert_with(|| now + interval.mul_f64(random::<f64>()));
+            *due = (*due).min(now + interval);
+        }
+    }
+
+    fn probe_due(&mut self, prober: &Prober) {
+        let due = self.take_due(Instant::now());
+        self.send(prober, due);
+    }
+
+    fn probe_all(&mut self, prober: &Prober) {
+        let all = self.take_all(Instant::now());
+        self.send(prober, all);
+    }
+
+    /// Sites due a probe by `now`, moved on to their next time.
+    fn take_due(&mut self, now: Instant) -> Vec<DueProbe> {
+        let mut probes = Vec::new();
+        let mut delay = Duration::ZERO;
+        for (name, config) in &self.sites {
+            let interval = self.interval_of(name, config);
+            let Some(due) = self.due.get_mut(name) else {
+                continue;
+            };
+            if *due <= now {
+                let hops = hop_round_due(&mut self.hops_due, name, config, self.interval, now);
+                probes.push(DueProbe {
+                    name: name.clone(),
+                    delay,
+                    hops,
+                });
+                delay += self.spacing;
+                // Stay on the same cadence unless we've fallen a whole interval behind.
+                *due = (*due + interval).max(now);
+            }
+        }
+        probes
+    }
+
+    /// Every site, on top of the schedule.
+    fn take_all(&mut self, now: Instant) -> Vec<DueProbe> {
+        let mut delay = Duration::ZERO;
+        let mut probes = Vec::new();
+        for (name, config) in &self.sites {
+            let hops = hop_round_due(&mut self.hops_due, name, config, self.interval, now);
+            probes.push(DueProbe {
+                name: name.clone(),
+                delay,
+                hops,
+            });
+            delay += self.spacing;
+        }
+        probes
+    }
+
+    fn send(&self, prober: &Prober, probes: Vec<DueProbe>) {
+        for probe in probes {
+            if let Some(config) = self.sites.get(&probe.name) {
+                prober.probe(
+                    &probe.name,
+                    config,
+                    self.timeout,
+                    self.payload,
+                    probe.delay,
+                    probe.hops,
+                );
+            }
+        }
+    }
+
+    fn next_due(&self) -> Instant {
+        self.due
+            .values()
+            .min()
+            .copied()
+            .unwrap_or_else(|| Instant::now() + Duration::from_secs(DEFAULT_INTERVAL))
+    }
+}
+
+/// Whether an mtr site's probe should do a hop round too.  Hop rounds stay on the site's normal interval
+/// while it's failing, a round of TTL probes every couple seconds would just add to the trouble.
+fn hop_round_due(
+    hops_due: &mut BTreeMap<String, Instant>,
+    name: &str,
+    config: &SiteConfig,
+    interval: u64,
+    now: Instant,
+) -> bool {
+    if !config.mtr {
+        return false;
+    }
+    if hops_due.get(name).is_some_and(|due| *due > now) {
+        return false;
+    }
+    // A little early is fine, probes on the normal interval can come in just ahead of it.
+    let interval = Duration::from_secs(config.interval.unwrap_or(interval).max(1));
+    hops_due.insert(name.to_string(), now + interval.mul_f64(0.9));
+    true
+}
+
+/// Probes every site on its own interval until the main loop goes away.  Failing sites are probed more often.
+pub async fn run_scheduler(
+    mut rx: UnboundedReceiver<ScheduleEvent>,
+    prober: Prober,
+    sites: BTreeMap<String, SiteConfig>,
+    timeout: u64,
+    payload: Payload,
+    settings: Settings,
+) {
+    let mut schedule = Schedule {
+        sites,
+        interval: DEFAULT_INTERVAL,
+        timeout,
+        payload,
+        due: BTreeMap::new(),
+        outage_interval: settings.outage_interval,
+        stable_after: settings.stable_after,
+        spacing: Duration::from_millis(settings.probe_spacing_ms),
+        failing: BTreeMap::new(),
+        hops_due: BTreeMap::new(),
+    };
+    schedule.reschedule();
+    let mut results = prober.results.subscribe();
+
+    loop {
+        tokio::select! {
+            event = rx.recv() => match event {
+                Some(ScheduleEvent::Sites(sites)) => {
+                    prober.set_sites(&schedule.sites, &sites);
+                    schedule.sites = sites;
+                    schedule.reschedule();
+                }
+                Some(ScheduleEvent::Interval(interval)) => {
+                    schedule.interval = interval;
+                    schedule.reschedule();
+                }
+                Some(ScheduleEvent::Timeout(timeout)) => schedule.timeout = timeout,
+                Some(ScheduleEvent::Payload(payload)) => schedule.payload = payload,
+                Some(ScheduleEvent::ProbeNow) => schedule.probe_all(&prober),
+                None => break,
+            },
+            result = results.recv() => match result {
+                Ok(response) => schedule.record(&response),
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!(skipped = n, "Scheduler fell behind on ping results");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = sleep_until(schedule.next_due()) => schedule.probe_due(&prober),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(sites: serde_json::Value) -> Schedule {
+        Schedule {
+            sites: serde_json::from_value(sites).unwrap(),
+            interval: 30,
+            timeout: 2,
+            payload: Payload::Tiny,
+            due: BTreeMap::new(),
+            outage_interval: 2,
+            stable_after: 3,
+            spacing: Duration::from_millis(10),
+            failing: BTreeMap::new(),
+            hops_due: BTreeMap::new(),
+        }
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn intervals() {
+        let mut schedule = schedule(serde_json::json!({
+            "a": {"address": "10.0.0.1"},
+            "b": {"address": "10.0.0.2", "interval": 5},
+            "c": {"address": "10.0.0.3", "interval": 0}
+        }));
+        let interval =
+            |schedule: &Schedule, name: &str| schedule.interval_of(name, &schedule.sites[name]);
+        assert_eq!(interval(&schedule, "a"), secs(30));
+        assert_eq!(interval(&schedule, "b"), secs(5));
+        assert_eq!(interval(&schedule, "c"), secs(1));
+        schedule.interval = 60;
+        assert_eq!(interval(&schedule, "a"), secs(60));
+        assert_eq!(interval(&schedule, "b"), secs(5));
+    }
+
+    #[test]
+    fn new_sites_start_within_their_first_interval() {
+        let mut schedule = schedule(serde_json::json!({
+            "a": {"address": "10.0.0.1"},
+            "b": {"address": "10.0.0.2", "interval": 5}
+        }));
+        let before = Instant::now();
+        schedule.reschedule();
+        let after = Instant::now();
+        assert!(schedule.due["a"] >= before && schedule.due["a"] <= after + secs(30));
+        assert!(schedule.due["b"] >= before && schedule.due["b"] <= after + secs(5));
+    }
+
+    #[test]
+    fn reschedule_keeps_places_and_drops_removed_sites() {
+        let mut schedule = schedule(serde_json::json!({
+            "a": {"address": "10.0.0.1"},
+            "b": {"address": "10.0.0.2"}
+        }));
+        let now = Instant::now();
+        schedule.due.insert("a".to_string(), now + secs(20));
+        schedule.due.insert("b".to_string(), now + secs(20));
+        schedule.failing.insert("b".to_string(), 0);
+        schedule.hops_due.insert("b".to_string(), now);
+
+        // Same interval, same place.
+        schedule.reschedule();
+        assert_eq!(schedule.due["a"], now + secs(20));
+        // A shorter one takes effect straight away.
+        schedule.interval = 10;
+        schedule.reschedule();
+        assert!(schedule.due["a"] <= Instant::now() + secs(10));
+        assert!(schedule.due["a"] < now + secs(20));
+
+        schedule.sites.remove("b");
+        schedule.reschedule();
+        assert!(!schedule.due.contains_key("b"));
+        assert!(!schedule.failing.contains_key("b"));
+        assert!(!schedule.hops_due.contains_key("b"));
+    }
+
+    #[test]
+    fn due_sites_move_on_an_interval() {
+        let mut schedule = schedule(serde_json::json!({
+            "a": {"address": "10.0.0.1"},
+            "b": {"address": "10.0.0.2"},
+            "c": {"address": "10.0.0.3"}
+        }));
+        let now = Instant::now() + secs(3600);
+        schedule.due.insert("a".to_string(), now - secs(5));
+        schedule.due.insert("b".to_string(), now - secs(300));
+        schedule.due.insert("c".to_string(), now + secs(5));
+
+        let due: Vec<String> = schedule.take_due(now).into_iter().map(|p| p.name).collect();
+        assert_eq!(due, ["a", "b"]);
+        // On the same cadence...
+        assert_eq!(schedule.due["a"], now + secs(25));
+        // ...unless it's fallen behind, then it catches up once rather than going out again and again.
+        assert_eq!(schedule.due["b"], now);
+        assert_eq!(schedule.due["c"], now + secs(5));
+    }
+
+    fn response(name: &str, status: PingStatus) -> PingResponse {
+        PingResponse {
+            name: name.to_string(),
+            response: (status == PingStatus::Up).then(|| Duration::from_millis(5)),
+            status,
+            error: String::new(),
+            response_v6: None,
+            status_v6: None,
+            parent_down: false,
+            stale: false,
+            round: 1,
+        }
+    }
+
+    #[test]
+    fn failing_sites_go_on_the_outage_interval_until_stable() {
+        let mut schedule = schedule(serde_json::json!({
+            "a": {"address": "10.0.0.1"},
+            "b": {"address": "10.0.0.2", "interval": 1}
+        }));
+        let later = Instant::now() + secs(30);
+        schedule.due.insert("a".to_string(), later);
+
+        schedule.record(&response("a", PingStatus::Timeout));
+        assert_eq!(schedule.interval_of("a", &schedule.sites["a"]), secs(2));
+        // The next probe is pulled in rather than waiting out the normal interval.
+        assert!(schedule.due["a"] <= Instant::now() + secs(2));
+        // Sites already probed more often than that stay as they are.
+        schedule.record(&response("b", PingStatus::Timeout));
+        assert_eq!(schedule.interval_of("b", &schedule.sites["b"]), secs(1));
+
+        // Another failure starts the count of replies over.
+        schedule.record(&response("a", PingStatus::Up));
+        schedule.record(&response("a", PingStatus::Up));
+        schedule.record(&response("a", PingStatus::Timeout));
+        schedule.record(&response("a", PingStatus::Up));
+        schedule.record(&response("a", PingStatus::Up));
+        assert!(schedule.failing.contains_key("a"));
+        schedule.record(&response("a", PingStatus::Up));
+        assert!(!schedule.failing.contains_key("a"));
+        assert_eq!(schedule.interval_of("a", &schedule.sites["a"]), secs(30));
+    }
+
+    #[test]
+    fn results_for_other_sites_are_ignored() {
+        let mut schedule = schedule(serde_json::json!({"a": {"address": "10.0.0.1"}}));
+        schedule.record(&response("gone", PingStatus::Timeout));
+        assert!(schedule.failing.is_empty());
+        // Replies from sites that weren't failing change nothing.
+        schedule.record(&response("a", PingStatus::Up));
+        assert!(schedule.failing.is_empty());
+    }
+
+    #[test]
+    fn hop_rounds_stay_on_the_normal_interval() {
+        let config: BTreeMap<String, SiteConfig> = serde_json::from_value(serde_json::json!({
+            "plain": {"address": "10.0.0.1"},
+            "mtr": {"address": "10.0.0.2", "mtr": true, "interval": 10}
+        }))
+        .unwrap();
+        let mut hops_due = BTreeMap::new();
+        let now = Instant::now();
+        assert!(!hop_round_due(
+            &mut hops_due,
+            "plain",
+            &config["plain"],
+            30,
+            now
+        ));
+        assert!(hop_round_due(&mut hops_due, "mtr", &config["mtr"], 30, now));
+        // Not again on the outage interval's probes.
+        assert!(!hop_round_due(
+            &mut hops_due,
+            "mtr",
+            &config["mtr"],
+            30,
+            now + secs(2)
+        ));
+        // A probe a little early on the normal interval still gets one.
+        assert!(hop_round_due(
+            &mut hops_due,
+            "mtr",
+            &config["mtr"],
+            30,
+            now + secs(9)
+        ));
+    }
+
+    #[test]
+    fn probes_due_together_are_spaced_out() {
+        let mut schedule = schedule(serde_json::json!({
+            "a": {"address": "10.0.0.1"},
+            "b": {"address": "10.0.0.2"},
+            "c": {"address": "10.0.0.3"}
+        }));
+        let now = Instant::now();
+        for name in ["a", "b", "c"] {
+            schedule.due.insert(name.to_string(), now);
+        }
+        let delays: Vec<Duration> = schedule.take_due(now).iter().map(|p| p.delay).collect();
+        assert_eq!(
+            delays,
+            [
+                Duration::ZERO,
+                Duration::from_millis(10),
+                Duration::from_millis(20)
+            ]
+        );
+
+        // "Refresh now" is spaced the same, and leaves the schedule alone.
+        let due = schedule.due.clone();
+        let delays: Vec<Duration> = schedule.take_all(now).iter().map(|p| p.delay).collect();
+        assert_eq!(
+            delays,
+            [
+                Duration::ZERO,
+                Duration::from_millis(10),
+                Duration::from_millis(20)
+            ]
+        );
+        assert_eq!(schedule.due, due);
+    }
+}
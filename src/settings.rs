@@ -0,0 +1,67 @@
+use super::*;
+
+use std::net::SocketAddr;
+
+/// Socket options for pings.  Set for every site in settings.json, or for one site in sites.json.  
+#[derive(Deserialize, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(default)]
+pub struct SocketOptions {
+    pub source: Option<IpAddr>,    // Send pings from this local address.
+    pub interface: Option<String>, // Send pings out this interface, e.g. "eth1".  Linux only.
+    pub ttl: Option<u32>,          // Hop limit for pings.
+    pub dscp: Option<u8>,          // DSCP marking (0-63) for pings.  IPv4 on Linux/macOS only.
+}
+impl SocketOptions {
+    /// These options, with anything left out taken from `fallback`.
+    pub fn or(&self, fallback: &SocketOptions) -> SocketOptions {
+        SocketOptions {
+            source: self.source.or(fallback.source),
+            interface: self.interface.clone().or(fallback.interface.clone()),
+            ttl: self.ttl.or(fallback.ttl),
+            dscp: self.dscp.or(fallback.dscp),
+        }
+    }
+}
+
+/// Optional settings.json next to sites.json.  Everything has a default, so the file can be left out.  
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct Settings {
+    pub http_listen: Option<SocketAddr>, // Address for the built-in web server, off when missing.
+    pub api_token: Option<String>,       // Bearer token for the control API, off when missing.
+    pub maintenance: Vec<MaintenanceWindow>, // Left out of availability reports.
+    pub log_level: String, // "error", "warn", "info", "debug" or "trace".  Also takes per-module filters.
+    pub log_dir: String,   // Log files go here, a new one each day.
+    pub log_files: usize,  // Number of daily log files to keep.
+    pub outage_interval: u64, // Seconds between pings of a failing site.
+    pub stable_after: u32, // Replies in a row before a failing site goes back to its normal interval.
+    pub max_probes: usize, // Most pings out at once.
+    pub probe_spacing_ms: u64, // Gap between pings that are due at the same time.
+    #[serde(flatten)]
+    pub socket: SocketOptions, // Defaults for every site.
+}
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            http_listen: None,
+            api_token: None,
+            maintenance: Vec::new(),
+            log_level: "info".to_string(),
+            log_dir: "logs".to_string(),
+            log_files: 14,
+            outage_interval: 2,
+            stable_after: 3,
+            max_probes: 64,
+            probe_spacing_ms: 20,
+            socket: SocketOptions::default(),
+        }
+    }
+}
+
+/// Reads settings.json if there is one.  Panics if the file exists but can't be parsed.  
+pub fn read_settings() -> Settings {
+    match fs::read_to_string(Path::new("settings.json")) {
+        Ok(data) => serde_json::from_str(&data).expect("Unable to deserialize settings.json"),
+        Err(_) => Settings::default(),
+    }
+}
@@ -0,0 +1,351 @@
+use super::*;
+
+use serde_json::{Map, Value};
+use std::ops::Range;
+
+/// A top level `"name": value` pair in sites.json, as byte ranges into the file.
+struct Member {
+    name: String,
+    key: Range<usize>,
+    value: Range<usize>,
+}
+
+/// Rewrites only the sites that changed between `old` and `new`, so the rest of the file keeps its
+/// hand formatting.  `old` must be what `original` parses to.  Renamed sites stay where they were.
+/// None if the file is laid out in a way this can't follow, the caller should reformat it instead.
+pub fn splice_sites(
+    original: &str,
+    old: &Map<String, Value>,
+    new: &Map<String, Value>,
+) -> Option<String> {
+    let (members, close) = members(original)?;
+    let indent = members
+        .last()
+        .map(|m| line_indent(original, m.key.start))
+        .unwrap_or("  ");
+    // New entries are laid out like the last one in the file.
+    let multiline = members
+        .last()
+        .is_some_and(|m| original[m.value.clone()].contains('\n'));
+
+    let mut edits: Vec<(Range<usize>, String)> = Vec::new();
+    let mut renamed = vec![false; members.len()];
+    let mut added = String::new();
+    for (i, (name, value)) in new.iter().enumerate() {
+        let member = match members.iter().position(|m| m.name == *name) {
+            Some(index) => &members[index],
+            // Takes the place of a site that's gone, it's been renamed.
+            None => match members.get(i).filter(|m| !new.contains_key(&m.name)) {
+                Some(member) => {
+                    renamed[i] = true;
+                    edits.push((member.key.clone(), serde_json::to_string(name).ok()?));
+                    member
+                }
+                None => {
+                    let value = render(value, multiline, indent);
+                    added.push_str(&format!(
+                        ",\n{indent}{}: {value}",
+                        serde_json::to_string(name).ok()?
+                    ));
+                    continue;
+                }
+            },
+        };
+        if old.get(&member.name) != Some(value) {
+            let multiline = original[member.value.clone()].contains('\n');
+            edits.push((member.value.clone(), render(value, multiline, indent)));
+        }
+    }
+    for (i, member) in members.iter().enumerate() {
+        if new.contains_key(&member.name) || renamed[i] {
+            continue;
+        }
+        // Take the comma with it, the one after it or the last one before it.
+        let range = match (members.get(i + 1), i.checked_sub(1)) {
+            (Some(next), _) => member.key.start..next.key.start,
+            (None, Some(prev)) => members[prev].value.end..member.value.end,
+            (None, None) => member.key.start..member.value.end,
+        };
+        edits.push((range, String::new()));
+    }
+    if !added.is_empty() {
+        match members.last() {
+            Some(last) => edits.push((last.value.end..last.value.end, added)),
+            None => edits.push((close..close, format!("{}\n", &added[1..]))),
+        }
+    }
+
+    // Back to front, so earlier ranges are still where they were.
+    edits.sort_by_key(|(range, _)| std::cmp::Reverse((range.start, range.end)));
+    let mut data = original.to_string();
+    let mut limit = data.len();
+    for (range, text) in edits {
+        // Overlapping edits, e.g. two neighbours removed at the end.  Let the caller reformat instead.
+        if range.end > limit {
+            return None;
+        }
+        limit = range.start;
+        data.replace_range(range, &text);
+    }
+    // Anything unexpected in the file could throw the edits off, make sure it says what it should.
+    match serde_json::from_str::<Map<String, Value>>(&data) {
+        Ok(written) if written == *new && written.keys().eq(new.keys()) => Some(data),
+        _ => None,
+    }
+}
+
+/// Lays out a site's entry: on one line, or over several lines at the given indent.
+fn render(value: &Value, multiline: bool, indent: &str) -> String {
+    if !multiline {
+        return inline(value);
+    }
+    let mut data = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+    if value
+        .serialize(&mut serde_json::Serializer::with_formatter(
+            &mut data, formatter,
+        ))
+        .is_err()
+    {
+        return inline(value);
+    }
+    String::from_utf8_lossy(&data).replace('\n', &format!("\n{indent}"))
+}
+
+/// One line JSON with spaces after colons & commas, the way people write it by hand.
+fn inline(value: &Value) -> String {
+    let string = |s: &str| serde_json::to_string(s).unwrap_or_default();
+    match value {
+        Value::Object(fields) if !fields.is_empty() => {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(k, v)| format!("{}: {}", string(k), inline(v)))
+                .collect();
+            format!("{{ {} }}", fields.join(", "))
+        }
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(inline).collect();
+            format!("[{}]", items.join(", "))
+        }
+        other => other.to_string(),
+    }
+}
+
+/// The whitespace a line starts with, for the line `pos` is on.  Empty if there's anything else before `pos`.
+fn line_indent(text: &str, pos: usize) -> &str {
+    let start = text[..pos].rfind('\n').map_or(0, |n| n + 1);
+    let indent = &text[start..pos];
+    if indent.trim().is_empty() {
+        indent
+    } else {
+        ""
+    }
+}
+
+/// The top level members of a JSON object, and where its closing brace is.
+fn members(text: &str) -> Option<(Vec<Member>, usize)> {
+    let bytes = text.as_bytes();
+    let mut i = skip_space(bytes, 0);
+    if *bytes.get(i)? != b'{' {
+        return None;
+    }
+    let mut members = Vec::new();
+    i = skip_space(bytes, i + 1);
+    if *bytes.get(i)? == b'}' {
+        return Some((members, i));
+    }
+    loop {
+        let key = i..string_end(bytes, i)?;
+        let name = serde_json::from_str(&text[key.clone()]).ok()?;
+        i = skip_space(bytes, key.end);
+        if *bytes.get(i)? != b':' {
+            return None;
+        }
+        let start = skip_space(bytes, i + 1);
+        let value = start..value_end(bytes, start)?;
+        i = skip_space(bytes, value.end);
+        members.push(Member { name, key, value });
+        match *bytes.get(i)? {
+            b',' => i = skip_space(bytes, i + 1),
+            b'}' => return Some((members, i)),
+            _ => return None,
+        }
+    }
+}
+
+fn skip_space(bytes: &[u8], mut i: usize) -> usize {
+    while bytes.get(i).is_some_and(|b| b.is_ascii_whitespace()) {
+        i += 1;
+    }
+    i
+}
+
+/// Just past the closing quote of the string starting at `i`.
+fn string_end(bytes: &[u8], i: usize) -> Option<usize> {
+    if *bytes.get(i)? != b'"' {
+        return None;
+    }
+    let mut j = i + 1;
+    loop {
+        match *bytes.get(j)? {
+            b'\\' => j += 2,
+            b'"' => return Some(j + 1),
+            _ => j += 1,
+        }
+    }
+}
+
+/// Just past the end of the value starting at `i`.
+fn value_end(bytes: &[u8], i: usize) -> Option<usize> {
+    match *bytes.get(i)? {
+        b'"' => string_end(bytes, i),
+        b'{' | b'[' => {
+            let mut depth = 0;
+            let mut j = i;
+            loop {
+                match *bytes.get(j)? {
+                    b'"' => {
+                        j = string_end(bytes, j)?;
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(j + 1);
+                        }
+                    }
+                    _ => {}
+                }
+                j += 1;
+            }
+        }
+        _ => {
+            let mut j = i;
+            while bytes
+                .get(j)
+                .is_some_and(|b| !b.is_ascii_whitespace() && !b",}]".contains(b))
+            {
+                j += 1;
+            }
+            (j > i).then_some(j)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SITES: &str = r#"{
+  "a":   "10.0.0.1",
+  "b": { "address": "10.0.0.2", "group": "X" },
+  "c": {
+    "address": "10.0.0.3"
+  }
+}
+"#;
+
+    fn map(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(map) => map,
+            _ => panic!("not an object"),
+        }
+    }
+
+    fn splice(new: Value) -> Option<String> {
+        let old = serde_json::from_str(SITES).unwrap();
+        splice_sites(SITES, &old, &map(new))
+    }
+
+    #[test]
+    fn only_the_edited_site_changes() {
+        let spliced = splice(json!({
+            "a": "10.0.0.1",
+            "b": {"address": "10.0.0.2", "group": "Y"},
+            "c": {"address": "10.0.0.3"}
+        }));
+        assert_eq!(
+            spliced.unwrap(),
+            SITES.replace(r#""group": "X""#, r#""group": "Y""#)
+        );
+    }
+
+    #[test]
+    fn multiline_entries_stay_multiline() {
+        let spliced = splice(json!({
+            "a": "10.0.0.1",
+            "b": {"address": "10.0.0.2", "group": "X"},
+            "c": {"address": "10.0.0.3", "mtr": true}
+        }));
+        assert_eq!(
+            spliced.unwrap(),
+            SITES.replace("\"10.0.0.3\"\n  }", "\"10.0.0.3\",\n    \"mtr\": true\n  }")
+        );
+    }
+
+    #[test]
+    fn renamed_site_keeps_its_place() {
+        let spliced = splice(json!({
+            "a": "10.0.0.1",
+            "bb": {"address": "10.0.0.2", "group": "X"},
+            "c": {"address": "10.0.0.3"}
+        }));
+        assert_eq!(spliced.unwrap(), SITES.replace("\"b\":", "\"bb\":"));
+    }
+
+    #[test]
+    fn removed_sites_take_their_comma() {
+        let spliced = splice(json!({
+            "a": "10.0.0.1",
+            "c": {"address": "10.0.0.3"}
+        }));
+        assert_eq!(
+            spliced.unwrap(),
+            SITES.replace(
+                "  \"b\": { \"address\": \"10.0.0.2\", \"group\": \"X\" },\n",
+                ""
+            )
+        );
+
+        let spliced = splice(json!({
+            "a": "10.0.0.1",
+            "b": {"address": "10.0.0.2", "group": "X"}
+        }));
+        assert_eq!(
+            spliced.unwrap(),
+            SITES.replace(",\n  \"c\": {\n    \"address\": \"10.0.0.3\"\n  }", "")
+        );
+    }
+
+    #[test]
+    fn added_sites_look_like_the_last_one() {
+        let spliced = splice(json!({
+            "a": "10.0.0.1",
+            "b": {"address": "10.0.0.2", "group": "X"},
+            "c": {"address": "10.0.0.3"},
+            "d": {"address": "10.0.0.4", "mtr": true}
+        }));
+        assert_eq!(
+            spliced.unwrap(),
+            SITES.replace(
+                "  }\n}\n",
+                "  },\n  \"d\": {\n    \"address\": \"10.0.0.4\",\n    \"mtr\": true\n  }\n}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn first_site_in_an_empty_file() {
+        let spliced = splice_sites("{}", &Map::new(), &map(json!({"a": "10.0.0.1"})));
+        assert_eq!(spliced.unwrap(), "{\n  \"a\": \"10.0.0.1\"\n}");
+    }
+
+    #[test]
+    fn gives_up_rather_than_guess() {
+        // Both of the last two removed, their commas overlap.
+        assert_eq!(splice(json!({"a": "10.0.0.1"})), None);
+        assert_eq!(splice_sites("[]", &Map::new(), &Map::new()), None);
+    }
+}
@@ -0,0 +1,157 @@
+use super::*;
+
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+/// Number of results kept per site for the history API.
+pub const STATUS_HISTORY_LEN: usize = 720;
+
+/// A site as shown on the web dashboard, mirrors a row in the site list.
+#[derive(Serialize, Clone)]
+pub struct ApiSite {
+    pub name: String,
+    pub group: Option<String>,
+    pub address: IpAddr,
+    pub address_v6: Option<IpAddr>,
+    pub status: &'static str, // "up", "down", "unreachable" or "unknown".
+    pub degraded: bool,       // Up, but one of a dual-stack site's addresses isn't answering.
+    pub response_ms: Option<f64>,
+    pub response_v6_ms: Option<f64>,
+    pub updated: Option<DateTime<Local>>,
+    pub error: Option<String>, // What went wrong, when it isn't up or is degraded.
+}
+
+/// A single result in a site's recent history.
+#[derive(Serialize, Clone)]
+pub struct HistoryPoint {
+    pub time: DateTime<Local>,
+    pub response_ms: Option<f64>,
+    pub response_v6_ms: Option<f64>,
+}
+
+fn millis(response: Option<Duration>) -> Option<f64> {
+    response.map(|r| r.as_secs_f64() * 1000.0)
+}
+
+#[derive(Default)]
+struct StatusInner {
+    config: BTreeMap<String, SiteConfig>,
+    last: BTreeMap<String, (PingResponse, DateTime<Local>)>,
+    history: BTreeMap<String, VecDeque<HistoryPoint>>,
+}
+
+/// Latest result & recent history for every site, shared between the result stream and the web server.
+#[derive(Clone, Default)]
+pub struct StatusStore(Arc<Mutex<StatusInner>>);
+impl StatusStore {
+    /// Brings the store in line with sites.json.  Removed sites are dropped.
+    pub fn set_sites(&self, sites: &BTreeMap<String, SiteConfig>) {
+        let mut inner = self.0.lock().unwrap();
+        inner.last.retain(|name, _| sites.contains_key(name));
+        inner.history.retain(|name, _| sites.contains_key(name));
+        inner.config = sites.clone();
+    }
+
+    pub fn add(&self, response: &PingResponse) {
+        let mut inner = self.0.lock().unwrap();
+        if !inner.config.contains_key(&response.name) {
+            return;
+        }
+        // Late answer to an earlier ping.
+        if let Some((last, _)) = inner.last.get(&response.name) {
+            if response.round <= last.round {
+                return;
+            }
+        }
+        let now = Local::now();
+        let history = inner.history.entry(response.name.clone()).or_default();
+        history.push_back(HistoryPoint {
+            time: now,
+            response_ms: millis(response.response),
+            response_v6_ms: millis(response.response_v6),
+        });
+        if history.len() > STATUS_HISTORY_LEN {
+            history.pop_front();
+        }
+        inner
+            .last
+            .insert(response.name.clone(), (response.clone(), now));
+    }
+
+    /// Every site in name order, with the same unreachable handling as the GUI.
+    pub fn sites(&self) -> Vec<ApiSite> {
+        let inner = self.0.lock().unwrap();
+        let mut responses: Vec<PingResponse> = inner
+            .config
+            .keys()
+            .map(|name| match inner.last.get(name) {
+                Some((response, _)) => response.clone(),
+                None => PingResponse {
+                    name: name.clone(),
+                    response: None,
+                    status: PingStatus::Pending,
+                    error: String::new(),
+                    response_v6: None,
+                    status_v6: None,
+                    parent_down: false,
+                    stale: false,
+                    round: 0,
+                },
+            })
+            .collect();
+        mark_unreachable(&mut responses, &inner.config);
+
+        responses
+            .into_iter()
+            .map(|r| {
+                let config = &inner.config[&r.name];
+                let updated = inner.last.get(&r.name).map(|(_, t)| *t);
+                let status = match (updated, r.is_err(), r.parent_down) {
+                    (None, _, _) => "unknown",
+                    (_, false, _) => "up",
+                    (_, true, true) => "unreachable",
+                    (_, true, false) => "down",
+                };
+                ApiSite {
+                    group: config.group.clone(),
+                    address: config.address,
+                    address_v6: config.address_v6,
+                    status,
+                    degraded: r.degraded(),
+                    response_ms: millis(r.response),
+                    response_v6_ms: millis(r.response_v6),
+                    updated,
+                    error: ((r.is_err() || r.degraded()) && !r.error.is_empty())
+                        .then(|| r.error.clone()),
+                    name: r.name,
+                }
+            })
+            .collect()
+    }
+
+    /// Recent results for a site, oldest first.  None if there's no such site.
+    pub fn history(&self, name: &str) -> Option<Vec<HistoryPoint>> {
+        let inner = self.0.lock().unwrap();
+        if !inner.config.contains_key(name) {
+            return None;
+        }
+        Some(
+            inner
+                .history
+                .get(name)
+                .map(|h| h.iter().cloned().collect())
+                .unwrap_or_default(),
+        )
+    }
+}
+
+/// Feeds every ping result into the status store.
+pub async fn record_status(store: StatusStore, mut rx: broadcast::Receiver<PingResponse>) {
+    loop {
+        match rx.recv().await {
+            Ok(response) => store.add(&response),
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
@@ -0,0 +1,401 @@
+use super::*;
+
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::mem::MaybeUninit;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+/// Gives up on the trace after this many hops.
+pub const MAX_HOPS: u8 = 30;
+
+/// A single hop along the path to a site.
+#[derive(Lens, Clone, PartialEq, Data)]
+pub struct TraceHop {
+    pub ttl: u8,
+    pub addr: String,
+    pub response: Option<Duration>,
+}
+
+/// Traceroute results for the trace panel.  An empty name means no trace has been requested.
+#[derive(Lens, Clone, PartialEq, Data, Default)]
+pub struct TraceResult {
+    pub name: String,
+    pub hops: Vec<TraceHop>,
+    pub running: bool,
+    pub id: u64, // Tells this trace's hops from those of one it replaced, which may still be running.
+    pub error: String, // Why the trace stopped early, empty if it didn't.
+}
+impl TraceResult {
+    pub fn new(name: String, id: u64) -> Self {
+        TraceResult {
+            name,
+            hops: Vec::new(),
+            running: true,
+            id,
+            error: String::new(),
+        }
+    }
+}
+
+/// What came back for a single TTL limited probe.
+pub enum HopReply {
+    /// A router along the way reported the TTL expired (or the destination is unreachable).
+    Hop(IpAddr, Duration),
+    /// The site itself answered.
+    Destination(IpAddr, Duration),
+    /// Nothing came back in time.
+    Silent,
+}
+
+impl HopReply {
+    pub fn to_trace_hop(&self, ttl: u8) -> TraceHop {
+        let (addr, response) = match self {
+            HopReply::Hop(addr, dur) | HopReply::Destination(addr, dur) => {
+                (addr.to_string(), Some(*dur))
+            }
+            HopReply::Silent => ("*".to_string(), None),
+        };
+        TraceHop {
+            ttl,
+            addr,
+            response,
+        }
+    }
+}
+
+/// Raw ICMP socket for sending TTL limited echo requests.  Needs the same privileges as the ping clients.
+pub struct HopProber {
+    socket: Socket,
+    target: IpAddr,
+    ident: u16,
+}
+impl HopProber {
+    pub fn new(target: IpAddr) -> std::io::Result<Self> {
+        let socket = match target {
+            IpAddr::V4(_) => Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))?,
+            IpAddr::V6(_) => Socket::new(Domain::IPV6, Type::RAW, Some(Protocol::ICMPV6))?,
+        };
+        // Windows refuses to read from a raw socket that isn't bound (WSAEINVAL).
+        let any: IpAddr = match target {
+            IpAddr::V4(_) => std::net::Ipv4Addr::UNSPECIFIED.into(),
+            IpAddr::V6(_) => std::net::Ipv6Addr::UNSPECIFIED.into(),
+        };
+        socket.bind(&SockAddr::from(SocketAddr::new(any, 0)))?;
+        Ok(HopProber {
+            socket,
+            target,
+            ident: random(),
+        })
+    }
+
+    /// Sends one echo request with the given TTL and waits up to `timeout` for something to answer it.
+    pub fn probe(&self, ttl: u8, seq: u16, timeout: Duration) -> std::io::Result<HopReply> {
+        self.send(ttl, seq)?;
+        let sent = Instant::now();
+        while let Some(reply) = self.recv(sent + timeout)? {
+            if reply.seq == seq {
+                return Ok(reply.into_hop(sent));
+            }
+        }
+        Ok(HopReply::Silent)
+    }
+
+    /// Probes every hop at once, mtr style.  Returns one reply per TTL, up to the site itself.
+    pub fn probe_path(&self, timeout: Duration) -> std::io::Result<Vec<HopReply>> {
+        // Random base so replies from an earlier round can't be mistaken for this one.
+        let base: u16 = random();
+        let mut sent = Vec::new();
+        for ttl in 1..=MAX_HOPS {
+            self.send(ttl, base.wrapping_add(ttl as u16))?;
+            sent.push(Instant::now());
+        }
+
+        let mut replies: Vec<HopReply> = sent.iter().map(|_| HopReply::Silent).collect();
+        let deadline = Instant::now() + timeout;
+        while let Some(reply) = self.recv(deadline)? {
+            let index = reply.seq.wrapping_sub(base).wrapping_sub(1) as usize;
+            if index < replies.len() {
+                replies[index] = reply.into_hop(sent[index]);
+            }
+        }
+
+        // Anything past the first TTL the site answered is just the site again.
+        if let Some(end) = replies
+            .iter()
+            .position(|r| matches!(r, HopReply::Destination(..)))
+        {
+            replies.truncate(end + 1);
+        }
+        Ok(replies)
+    }
+
+    fn send(&self, ttl: u8, seq: u16) -> std::io::Result<()> {
+        match self.target {
+            IpAddr::V4(_) => self.socket.set_ttl(ttl as u32)?,
+            IpAddr::V6(_) => self.socket.set_unicast_hops_v6(ttl as u32)?,
+        }
+        let packet = echo_request(self.target, self.ident, seq);
+        self.socket
+            .send_to(&packet, &SockAddr::from(SocketAddr::new(self.target, 0)))?;
+        Ok(())
+    }
+
+    /// Waits for the next reply to one of our requests.  None once the deadline passes.
+    fn recv(&self, deadline: Instant) -> std::io::Result<Option<Reply>> {
+        // Raw sockets see every ICMP packet on the box, keep reading until ours shows up.
+        let mut buf = [MaybeUninit::<u8>::uninit(); 1500];
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            self.socket.set_read_timeout(Some(deadline - now))?;
+            let (len, from) = match self.socket.recv_from(&mut buf) {
+                Ok(r) => r,
+                Err(e)
+                    if e.kind() == std::io::ErrorKind::WouldBlock
+                        || e.kind() == std::io::ErrorKind::TimedOut =>
+                {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e),
+            };
+            let at = Instant::now();
+            // SAFETY: recv_from initialized the first `len` bytes.
+            let data: Vec<u8> = buf[..len]
+                .iter()
+                .map(|b| unsafe { b.assume_init() })
+                .collect();
+            let Some(addr) = from.as_socket().map(|s| s.ip()) else {
+                continue;
+            };
+            if let Some((seq, destination)) = parse_reply(self.target, self.ident, &data) {
+                return Ok(Some(Reply {
+                    seq,
+                    addr,
+                    at,
+                    destination,
+                }));
+            }
+            // Someone else's packet.
+        }
+    }
+}
+
+/// Sequence number of the reply and whether the site itself answered, or None if the packet isn't
+/// an answer to one of our requests to `target`.
+fn parse_reply(target: IpAddr, ident: u16, data: &[u8]) -> Option<(u16, bool)> {
+    let (icmp, echo_reply, errors, inner_header_len) = match target {
+        IpAddr::V4(_) => {
+            // IPv4 raw sockets hand us the IP header too.
+            let ihl = (*data.first()? as usize & 0x0f) * 4;
+            (data.get(ihl..)?, 0, [11, 3], None)
+        }
+        IpAddr::V6(_) => (data, 129, [3, 1], Some(40)),
+    };
+    let kind = *icmp.first()?;
+    if kind == echo_reply {
+        return our_seq(ident, icmp).map(|seq| (seq, true));
+    }
+    if errors.contains(&kind) {
+        // ICMP errors quote the original IP header followed by our echo request.
+        let inner = icmp.get(8..)?;
+        let inner_len = match inner_header_len {
+            Some(len) => len,
+            None => (*inner.first()? as usize & 0x0f) * 4,
+        };
+        return our_seq(ident, inner.get(inner_len..)?).map(|seq| (seq, false));
+    }
+    None
+}
+
+fn our_seq(ident: u16, icmp: &[u8]) -> Option<u16> {
+    if icmp.len() >= 8 && u16::from_be_bytes([icmp[4], icmp[5]]) == ident {
+        Some(u16::from_be_bytes([icmp[6], icmp[7]]))
+    } else {
+        None
+    }
+}
+
+/// A reply matched to one of our requests.
+struct Reply {
+    seq: u16,
+    addr: IpAddr,
+    at: Instant,
+    destination: bool,
+}
+impl Reply {
+    fn into_hop(self, sent: Instant) -> HopReply {
+        let rtt = self.at.saturating_duration_since(sent);
+        if self.destination {
+            HopReply::Destination(self.addr, rtt)
+        } else {
+            HopReply::Hop(self.addr, rtt)
+        }
+    }
+}
+
+/// Builds an ICMP echo request.  The kernel fills in the checksum for ICMPv6.
+fn echo_request(target: IpAddr, ident: u16, seq: u16) -> Vec<u8> {
+    let kind = match target {
+        IpAddr::V4(_) => 8,
+        IpAddr::V6(_) => 128,
+    };
+    let mut packet = vec![kind, 0, 0, 0];
+    packet.extend_from_slice(&ident.to_be_bytes());
+    packet.extend_from_slice(&seq.to_be_bytes());
+    packet.extend_from_slice(&[0; 32]);
+    if target.is_ipv4() {
+        let sum = checksum(&packet).to_be_bytes();
+        packet[2] = sum[0];
+        packet[3] = sum[1];
+    }
+    packet
+}
+
+/// RFC 1071 internet checksum.
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    for chunk in data.chunks(2) {
+        let word = match chunk {
+            [hi, lo] => u16::from_be_bytes([*hi, *lo]),
+            [hi] => u16::from_be_bytes([*hi, 0]),
+            _ => unreachable!(),
+        };
+        sum += word as u32;
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Traceroute a site.  Sends each hop back to the GUI thread as it's discovered, tagged with the trace's `id`.
+pub async fn traceroute(mut cx: ContextProxy, site: SiteAddress, timeout: u64, id: u64) {
+    // Raw socket reads block, keep them off the runtime threads.
+    let _ = tokio::task::spawn_blocking(move || {
+        let timeout = Duration::from_secs(timeout);
+        let error = match HopProber::new(site.addr) {
+            Ok(prober) => {
+                let mut error = String::new();
+                for ttl in 1..=MAX_HOPS {
+                    let reply = match prober.probe(ttl, ttl as u16, timeout) {
+                        Ok(reply) => reply,
+                        Err(e) => {
+                            warn!(site = %site.name, ttl, error = %e, "Traceroute probe failed");
+                            error = format!("Probe to hop {ttl} failed: {e}");
+                            break;
+                        }
+                    };
+                    let _ = cx.emit(ViziaEvent::TraceHop(id, reply.to_trace_hop(ttl)));
+                    if let HopReply::Destination(..) = reply {
+                        break;
+                    }
+                }
+                error
+            }
+            Err(e) => {
+                warn!(site = %site.name, error = %e, "Couldn't open raw socket for traceroute");
+                format!("Couldn't open a raw socket for the trace: {e}")
+            }
+        };
+        let _ = cx.emit(ViziaEvent::TraceFinished(id, error));
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENT: u16 = 0xbeef;
+    const V4: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 1));
+    const V6: IpAddr = IpAddr::V6(std::net::Ipv6Addr::LOCALHOST);
+
+    /// An IPv4 header with no options, as raw sockets hand it over.
+    fn ipv4_header() -> Vec<u8> {
+        let mut header = vec![0x45];
+        header.resize(20, 0);
+        header
+    }
+
+    /// An ICMP error (time exceeded, unreachable) quoting our request behind `inner_header`.
+    fn icmp_error(kind: u8, inner_header: Vec<u8>, request: Vec<u8>) -> Vec<u8> {
+        let mut packet = vec![kind, 0, 0, 0, 0, 0, 0, 0];
+        packet.extend(inner_header);
+        packet.extend(request);
+        packet
+    }
+
+    fn echo_reply(kind: u8, ident: u16, seq: u16) -> Vec<u8> {
+        let mut packet = vec![kind, 0, 0, 0];
+        packet.extend_from_slice(&ident.to_be_bytes());
+        packet.extend_from_slice(&seq.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn ipv4_echo_reply_is_the_destination() {
+        let mut data = ipv4_header();
+        data.extend(echo_reply(0, IDENT, 7));
+        assert_eq!(parse_reply(V4, IDENT, &data), Some((7, true)));
+        assert_eq!(parse_reply(V4, IDENT + 1, &data), None);
+    }
+
+    #[test]
+    fn ipv4_errors_quote_our_request() {
+        for kind in [11, 3] {
+            let mut data = ipv4_header();
+            data.extend(icmp_error(kind, ipv4_header(), echo_request(V4, IDENT, 12)));
+            assert_eq!(parse_reply(V4, IDENT, &data), Some((12, false)));
+        }
+    }
+
+    #[test]
+    fn ipv4_headers_with_options() {
+        // 24 byte headers, outside and quoted.
+        let header = || {
+            let mut header = vec![0x46];
+            header.resize(24, 0);
+            header
+        };
+        let mut data = header();
+        data.extend(icmp_error(11, header(), echo_request(V4, IDENT, 3)));
+        assert_eq!(parse_reply(V4, IDENT, &data), Some((3, false)));
+    }
+
+    #[test]
+    fn ipv6_replies() {
+        let reply = echo_reply(129, IDENT, 9);
+        assert_eq!(parse_reply(V6, IDENT, &reply), Some((9, true)));
+
+        for kind in [3, 1] {
+            let data = icmp_error(kind, vec![0x60; 40], echo_request(V6, IDENT, 4));
+            assert_eq!(parse_reply(V6, IDENT, &data), Some((4, false)));
+        }
+    }
+
+    #[test]
+    fn ignores_other_packets() {
+        // Our own request looping back, and someone else's reply.
+        let mut data = ipv4_header();
+        data.extend(echo_request(V4, IDENT, 1));
+        assert_eq!(parse_reply(V4, IDENT, &data), None);
+        let mut data = ipv4_header();
+        data.extend(echo_reply(0, 0x1234, 1));
+        assert_eq!(parse_reply(V4, IDENT, &data), None);
+        // Neighbour discovery.
+        assert_eq!(parse_reply(V6, IDENT, &echo_reply(135, IDENT, 1)), None);
+    }
+
+    #[test]
+    fn truncated_packets() {
+        assert_eq!(parse_reply(V4, IDENT, &[]), None);
+        assert_eq!(parse_reply(V4, IDENT, &ipv4_header()), None);
+        let mut data = ipv4_header();
+        data.extend(icmp_error(11, ipv4_header(), vec![8, 0, 0, 0, 0xbe]));
+        assert_eq!(parse_reply(V4, IDENT, &data), None);
+        assert_eq!(parse_reply(V6, IDENT, &[129, 0, 0, 0]), None);
+        assert_eq!(parse_reply(V6, IDENT, &[3, 0, 0, 0, 0, 0, 0, 0]), None);
+    }
+}
@@ -1,46 +1,84 @@
 use super::*;
 
-pub fn vizia_main(tx: mpsc::Sender<TokioEvent>) {
+use std::sync::{Arc, Mutex};
+
+/// Runs the GUI until the window closes.  Returns the tokio thread's supervisor to wait on.
+pub fn vizia_main() -> Option<std::thread::JoinHandle<()>> {
+    let supervisor = Arc::new(Mutex::new(None));
+    let started = supervisor.clone();
     // Spin up the GUI.
     let _ = Application::new(move |cx| {
-        // Create & send ContextProxy to Tokio thread for event messaging.
-        let proxy = cx.get_proxy();
-        let _ = tx.send(TokioEvent::EventProxy(proxy));
+        // Start the tokio thread with a ContextProxy for event messaging.
+        let (tx, handle) = start_worker(cx.get_proxy());
+        *started.lock().unwrap() = Some(handle);
 
-        // Create a timer that sends an event every second to update the gui
-        let timer = cx.add_timer(Duration::from_secs(1), None, |cx, action| {
-            if let TimerAction::Tick(_) = action {
-                cx.emit(ViziaEvent::TimerIncrement)
-            }
-        });
-
-        // Snapshot of current time.  Gets replaced pretty much immediately.
+        // Snapshot of current time.  Replaced by the time of each result as they come in.
         let current_time = Local::now();
 
-        // First round of pings.
-        let _ = tx.send(TokioEvent::TimerElapsed);
+        // First round of pings, the worker's schedule takes it from there.
+        if tx.send(TokioEvent::ProbeNow).is_err() {
+            error!("Couldn't start the first round of pings");
+        }
 
-        // Build sites list & history for GUI use.
-        let sites = sites_to_pings(read_sites());
+        // Build sites list, history & groups for GUI use.
+        let config = read_sites();
+        let mut sites = sites_to_pings(config.clone());
+        mark_unreachable(&mut sites, &config);
         let history = start_history(&sites);
+        let groups = build_groups(&sites, &history, &config, &[]);
+        let event_log = recent_log();
 
         // Create the data model for the GUI context.
         AppData {
             sites,
-            timer,
-            timer_count: 30,
             tx,
             menu_visible: false,
-            timer_duration: 30,
+            timer_duration: DEFAULT_INTERVAL as i32,
             current_time,
             show_average: false,
             history,
             payload: Payload::Tiny,
             timeout: 4,
+            trace: TraceResult::default(),
+            show_mtr: false,
+            mtr: Vec::new(),
+            groups,
+            config,
+            collapsed: Vec::new(),
+            sort: SortMode::Name,
+            filter: String::new(),
+            problems_only: false,
+            last_change: BTreeMap::new(),
+            logged_state: BTreeMap::new(),
+            last_seen: start_last_seen(&config, current_time),
+            tile_layout: false,
+            tile_columns: 1,
+            export_from: current_time.format("%Y-%m-01").to_string(),
+            export_to: current_time.format("%Y-%m-%d").to_string(),
+            export_status: String::new(),
+            show_log: false,
+            shown_log: event_log.clone(),
+            event_log,
+            log_filter: String::new(),
+            log_status: String::new(),
+            worker_alive: true,
+            worker_restarts: 0,
+            probes_in_flight: 0,
+            probes_skipped: 0,
+            ping_problem: String::new(),
+            sites_problem: String::new(),
+            discovery: Discovery::default(),
+            editor: SiteEditor::new(),
         }
         .build(cx);
 
-        cx.start_timer(timer);
+        // Check for stale results every few seconds.
+        let stale_timer = cx.add_timer(Duration::from_secs(5), None, |cx, action| {
+            if let TimerAction::Tick(_) = action {
+                cx.emit(ViziaEvent::StaleCheck)
+            }
+        });
+        cx.start_timer(stale_timer);
         cx.add_stylesheet(include_style!("style.css"))
             .expect("Failed to load style sheet!");
 
@@ -53,58 +91,540 @@ pub fn vizia_main(tx: mpsc::Sender<TokioEvent>) {
     })
     .title("MHUSD Site Monitor")
     .run();
+    let handle = supervisor.lock().unwrap().take();
+    handle
 }
 
 // Left side, site names and responses.
 fn left_side(cx: &mut Context) -> Handle<VStack> {
     VStack::new(cx, |cx| {
-        Binding::new(cx, AppData::show_average, |cx, show| {
-            if show.get(cx) {
-                List::new(cx, AppData::history, |cx, _, site| {
-                    HStack::new(cx, |cx| {
-                        Label::new(cx, site.then(SiteAverage::name)).class("siteName");
-                        Label::new(cx, site.then(SiteAverage::avg)).class("siteResponse");
-                    })
-                    .col_between(Stretch(1.0))
-                    .class("siteRow")
-                    .toggle_class(
-                        "siteRowError",
-                        site.then(SiteAverage::avg).map(|h| h.is_empty()),
-                    );
-                });
-            } else {
-                List::new(cx, AppData::sites, |cx, _, site| {
+        ping_problem(cx);
+        sites_problem(cx);
+        ScrollView::new(cx, 0.0, 0.0, false, true, |cx| {
+            List::new(cx, AppData::groups, |cx, _, group| {
+                VStack::new(cx, |cx| {
+                    group_header(cx, group);
+                    group_sites(cx, group);
+                })
+                .class("siteGroup");
+            });
+        })
+        .on_geo_changed(|ex, geo| {
+            if geo.contains(GeoChanged::WIDTH_CHANGED) {
+                let width = ex.bounds().w / ex.scale_factor();
+                ex.emit(ViziaEvent::ListWidthChanged(width))
+            }
+        })
+        .class("siteScroll");
+        trace_panel(cx);
+        mtr_panel(cx);
+        event_log_panel(cx);
+        discovery_panel(cx);
+        Label::new(
+            cx,
+            AppData::current_time.map(|t| format!("Last Update: {}", t.format("%r"))),
+        )
+        .class("timeStamp");
+    })
+    .class("leftPane")
+}
+
+// Group name & status summary.  Click to collapse/expand.
+fn group_header(cx: &mut Context, group: impl Lens<Target = SiteGroup>) {
+    Binding::new(cx, group.then(SiteGroup::name), move |cx, name| {
+        // Ungrouped sites don't get a header.
+        if name.get(cx).is_empty() {
+            return;
+        }
+        HStack::new(cx, |cx| {
+            Label::new(
+                cx,
+                group.map(|g| {
+                    let arrow = if g.collapsed { "+" } else { "-" };
+                    format!("{arrow} {}", g.name)
+                }),
+            )
+            .class("groupName");
+            Label::new(cx, group.map(|g| g.summary())).class("groupSummary");
+        })
+        .col_between(Stretch(1.0))
+        .class("groupRow")
+        .toggle_class("groupRowError", group.map(|g| g.down() > 0))
+        .on_press(move |ex| {
+            let name = group.then(SiteGroup::name).get(ex);
+            ex.emit(ViziaEvent::GroupTogglePressed(name))
+        });
+    });
+}
+
+// The sites in a group, as rows or tiles.  Nothing while the group is collapsed.
+fn group_sites(cx: &mut Context, group: impl Lens<Target = SiteGroup>) {
+    Binding::new(
+        cx,
+        group.then(SiteGroup::collapsed),
+        move |cx, collapsed| {
+            if collapsed.get(cx) {
+                return;
+            }
+            Binding::new(cx, AppData::tile_layout, move |cx, tiles| {
+                if tiles.get(cx) {
+                    site_tiles(cx, group);
+                    return;
+                }
+                Binding::new(cx, AppData::show_average, move |cx, show| {
+                    if show.get(cx) {
+                        List::new(cx, group.then(SiteGroup::history), |cx, _, site| {
+                            average_row(cx, site);
+                        });
+                    } else {
+                        List::new(cx, group.then(SiteGroup::sites), |cx, _, site| {
+                            site_row(cx, site);
+                        });
+                    }
+                }); // End of show_average Binding
+            });
+        },
+    );
+}
+
+// Sites as a grid of colored tiles, as many across as fit the window.
+fn site_tiles(cx: &mut Context, group: impl Lens<Target = SiteGroup>) {
+    Binding::new(cx, AppData::tile_columns, move |cx, columns| {
+        let columns = columns.get(cx).max(1);
+        Binding::new(
+            cx,
+            group.then(SiteGroup::sites).map(|s| s.len()),
+            move |cx, len| {
+                let len = len.get(cx);
+                VStack::new(cx, |cx| {
+                    for start in (0..len).step_by(columns) {
+                        HStack::new(cx, |cx| {
+                            for i in start..(start + columns).min(len) {
+                                site_tile(cx, group.then(SiteGroup::sites).index(i));
+                            }
+                        })
+                        .class("tileRow");
+                    }
+                })
+                .class("tileGrid");
+            },
+        );
+    });
+}
+
+// A single site tile.  Background color shows the status.
+fn site_tile(cx: &mut Context, site: impl Lens<Target = PingResponse>) {
+    VStack::new(cx, |cx| {
+        Label::new(cx, site.then(PingResponse::name)).class("tileName");
+        Label::new(
+            cx,
+            site.map(|s| {
+                if s.stale {
+                    "Stale / unknown".to_string()
+                } else if s.parent_down {
+                    "Unreachable".to_string()
+                } else {
+                    s.latency_text()
+                }
+            }),
+        )
+        .class("tileResponse");
+    })
+    .class("siteTile")
+    .tooltip(move |cx| site_tooltip(cx, site))
+    .toggle_class(
+        "siteTileError",
+        site.map(|s| s.is_down() && !s.parent_down && !s.stale),
+    )
+    .toggle_class(
+        "siteTileUnreachable",
+        site.map(|s| s.parent_down && !s.stale),
+    )
+    .toggle_class("siteTileStale", site.then(PingResponse::stale))
+    .toggle_class("siteTileDegraded", site.map(|s| s.degraded() && !s.stale));
+}
+
+// What the site's last result means, e.g. the actual reason a ping failed.
+fn site_tooltip(cx: &mut Context, site: impl Lens<Target = PingResponse>) -> Handle<Tooltip> {
+    Tooltip::new(cx, move |cx| {
+        Label::new(cx, site.map(|s| s.describe()));
+    })
+}
+
+// A site's most recent ping.
+fn site_row(cx: &mut Context, site: impl Lens<Target = PingResponse>) {
+    HStack::new(cx, |cx| {
+        Label::new(cx, site.then(PingResponse::name)).class("siteName");
+        Label::new(
+            cx,
+            site.map(|s| {
+                if s.stale {
+                    "Stale / unknown".to_string()
+                } else if s.parent_down {
+                    "Unreachable (parent down)".to_string()
+                } else {
+                    s.latency_text()
+                }
+            }),
+        )
+        .class("siteResponse");
+        Button::new(cx, |cx| Label::new(cx, "Trace"))
+            .on_press(move |ex| {
+                let name = site.then(PingResponse::name).get(ex);
+                ex.emit(ViziaEvent::TracePressed(name))
+            })
+            .class("traceButton");
+    })
+    .col_between(Stretch(1.0))
+    .class("siteRow")
+    .tooltip(move |cx| site_tooltip(cx, site))
+    .toggle_class(
+        "siteRowError",
+        site.map(|s| s.is_down() && !s.parent_down && !s.stale),
+    )
+    .toggle_class(
+        "siteRowUnreachable",
+        site.map(|s| s.parent_down && !s.stale),
+    )
+    .toggle_class("siteRowStale", site.then(PingResponse::stale))
+    .toggle_class("siteRowDegraded", site.map(|s| s.degraded() && !s.stale));
+}
+
+// A site's average ping since averaging was switched on.
+fn average_row(cx: &mut Context, site: impl Lens<Target = SiteAverage>) {
+    HStack::new(cx, |cx| {
+        Label::new(cx, site.then(SiteAverage::name)).class("siteName");
+        Label::new(cx, site.then(SiteAverage::avg)).class("siteResponse");
+    })
+    .col_between(Stretch(1.0))
+    .class("siteRow")
+    .toggle_class(
+        "siteRowError",
+        site.then(SiteAverage::avg).map(|h| h.is_empty()),
+    );
+}
+
+// Hop-by-hop results of the last traceroute.  Hidden until a trace is requested.
+fn trace_panel(cx: &mut Context) {
+    Binding::new(cx, AppData::trace.then(TraceResult::name), |cx, name| {
+        if name.get(cx).is_empty() {
+            return;
+        }
+        VStack::new(cx, |cx| {
+            HStack::new(cx, |cx| {
+                Label::new(
+                    cx,
+                    AppData::trace.map(|t| {
+                        if t.running {
+                            format!("Tracing {}...", t.name)
+                        } else {
+                            format!("Trace to {}", t.name)
+                        }
+                    }),
+                )
+                .class("traceTitle");
+                Button::new(cx, |cx| Label::new(cx, "Close"))
+                    .on_press(|ex| ex.emit(ViziaEvent::TraceClosed))
+                    .class("traceButton");
+            })
+            .col_between(Stretch(1.0))
+            .class("siteRow");
+            Binding::new(cx, AppData::trace.then(TraceResult::error), |cx, error| {
+                if error.get(cx).is_empty() {
+                    return;
+                }
+                Label::new(cx, error).text_wrap(true).class("traceError");
+            });
+            List::new(cx, AppData::trace.then(TraceResult::hops), |cx, _, hop| {
+                HStack::new(cx, |cx| {
+                    Label::new(cx, hop.map(|h| format!("{:>2}  {}", h.ttl, h.addr)))
+                        .class("siteName");
+                    Label::new(
+                        cx,
+                        hop.then(TraceHop::response).map(|r| {
+                            if let Some(resp) = r {
+                                format!("{resp:.2?}")
+                            } else {
+                                "*".to_string()
+                            }
+                        }),
+                    )
+                    .class("siteResponse");
+                })
+                .col_between(Stretch(1.0))
+                .class("traceRow")
+                .toggle_class(
+                    "siteRowError",
+                    hop.then(TraceHop::response).map(|r| r.is_none()),
+                );
+            });
+        })
+        .class("tracePane");
+    });
+}
+
+// Per-hop loss and latency for sites marked "mtr" in sites.json.
+fn mtr_panel(cx: &mut Context) {
+    Binding::new(cx, AppData::show_mtr, |cx, show| {
+        if !show.get(cx) {
+            return;
+        }
+        List::new(cx, AppData::mtr, |cx, _, site| {
+            VStack::new(cx, |cx| {
+                HStack::new(cx, |cx| {
+                    Label::new(cx, site.then(MtrSite::name)).class("traceTitle");
+                    Label::new(cx, "Loss   Last   Avg   Best   Worst").class("mtrHeader");
+                })
+                .col_between(Stretch(1.0))
+                .class("siteRow");
+                List::new(cx, site.then(MtrSite::hops), |cx, _, hop| {
                     HStack::new(cx, |cx| {
-                        Label::new(cx, site.then(PingResponse::name)).class("siteName");
+                        Label::new(cx, hop.map(|h| format!("{:>2}  {}", h.ttl, h.addr)))
+                            .class("siteName");
                         Label::new(
                             cx,
-                            site.then(PingResponse::response).map(|r| {
-                                if let Some(resp) = r {
-                                    format!("{resp:.2?}")
-                                } else {
-                                    "Timeout!".to_string()
-                                }
+                            hop.map(|h| {
+                                let fmt = |d: Option<Duration>| match d {
+                                    Some(d) => format!("{:.1}", d.as_secs_f64() * 1000.0),
+                                    None => "-".to_string(),
+                                };
+                                format!(
+                                    "{:.0}%   {}   {}   {}   {}",
+                                    h.loss(),
+                                    fmt(h.last),
+                                    fmt(h.avg()),
+                                    fmt(h.best),
+                                    fmt(h.worst)
+                                )
                             }),
                         )
                         .class("siteResponse");
                     })
                     .col_between(Stretch(1.0))
-                    .class("siteRow")
-                    .toggle_class("siteRowError", site.then(PingResponse::is_err));
+                    .class("traceRow")
+                    .toggle_class("siteRowError", hop.then(MtrHop::last).map(|l| l.is_none()));
                 });
-            }
-        }); // End of show_average Binding
-        Element::new(cx); //Exists to take up space
-        Label::new(
+            })
+            .class("tracePane");
+        });
+    });
+}
+
+// Sites going down & recovering, newest first.
+fn event_log_panel(cx: &mut Context) {
+    Binding::new(cx, AppData::show_log, |cx, show| {
+        if !show.get(cx) {
+            return;
+        }
+        VStack::new(cx, |cx| {
+            HStack::new(cx, |cx| {
+                Label::new(cx, "Event log").class("traceTitle");
+                Textbox::new(cx, AppData::log_filter)
+                    .on_edit(|ex, text| ex.emit(ViziaEvent::LogFilterChanged(text)))
+                    .class("logFilter");
+                Button::new(cx, |cx| Label::new(cx, "Export"))
+                    .on_press(|ex| ex.emit(ViziaEvent::LogExportPressed))
+                    .class("traceButton");
+            })
+            .col_between(Stretch(1.0))
+            .class("siteRow");
+            Label::new(cx, AppData::log_status).class("traceTitle");
+            ScrollView::new(cx, 0.0, 0.0, false, true, |cx| {
+                List::new(cx, AppData::shown_log, |cx, _, entry| {
+                    Label::new(cx, entry.then(LogEntry::text))
+                        .class("logEntry")
+                        .toggle_class("logEntryDown", entry.then(LogEntry::is_down));
+                });
+            })
+            .class("logScroll");
+        })
+        .class("tracePane");
+    });
+}
+
+// Hosts found by a discovery scan.  Tick the ones to add to sites.json.
+fn discovery_panel(cx: &mut Context) {
+    Binding::new(cx, AppData::discovery.then(Discovery::shown), |cx, show| {
+        if !show.get(cx) {
+            return;
+        }
+        VStack::new(cx, |cx| {
+            HStack::new(cx, |cx| {
+                Label::new(cx, AppData::discovery.then(Discovery::status)).class("traceTitle");
+                Button::new(cx, |cx| Label::new(cx, "Stop"))
+                    .on_press(|ex| ex.emit(ViziaEvent::DiscoveryStopPressed))
+                    .disabled(AppData::discovery.then(Discovery::running).map(|r| !r))
+                    .class("traceButton");
+                Button::new(cx, |cx| Label::new(cx, "Add selected"))
+                    .on_press(|ex| ex.emit(ViziaEvent::DiscoveryAddPressed))
+                    .class("traceButton");
+                Button::new(cx, |cx| Label::new(cx, "Close"))
+                    .on_press(|ex| ex.emit(ViziaEvent::DiscoveryClosed))
+                    .class("traceButton");
+            })
+            .col_between(Pixels(10.0))
+            .class("siteRow");
+            ScrollView::new(cx, 0.0, 0.0, false, true, |cx| {
+                List::new(
+                    cx,
+                    AppData::discovery.then(Discovery::hosts),
+                    |cx, index, host| {
+                        HStack::new(cx, |cx| {
+                            Checkbox::new(cx, host.then(FoundHost::selected))
+                                .on_toggle(move |ex| ex.emit(ViziaEvent::DiscoveryToggled(index)));
+                            Label::new(cx, host.then(FoundHost::name)).class("siteName");
+                            Label::new(
+                                cx,
+                                host.map(|h| {
+                                    if h.hostname.is_empty() {
+                                        h.addr.clone()
+                                    } else {
+                                        format!("{} ({})", h.addr, h.hostname)
+                                    }
+                                }),
+                            )
+                            .class("discoveryAddress");
+                            Label::new(
+                                cx,
+                                host.then(FoundHost::response).map(|r| match r {
+                                    Some(resp) => format!("{resp:.2?}"),
+                                    None => String::new(),
+                                }),
+                            )
+                            .class("siteResponse");
+                        })
+                        .col_between(Pixels(10.0))
+                        .class("traceRow");
+                    },
+                );
+            })
+            .class("logScroll");
+        })
+        .class("tracePane");
+    });
+}
+
+// Add, edit, disable & remove sites.  Pick a site from the list to edit it.
+fn site_editor(cx: &mut Context) {
+    VStack::new(cx, |cx| {
+        Label::new(cx, "Edit sites: ").class("menuToggleLabel");
+        ScrollView::new(cx, 0.0, 0.0, false, true, |cx| {
+            List::new(
+                cx,
+                AppData::editor.then(SiteEditor::sites),
+                |cx, index, site| {
+                    Label::new(
+                        cx,
+                        site.map(|s| {
+                            if s.disabled {
+                                format!("{} (disabled)", s.name)
+                            } else {
+                                s.name.clone()
+                            }
+                        }),
+                    )
+                    .class("editorSite")
+                    .toggle_class(
+                        "editorSiteSelected",
+                        AppData::editor
+                            .map(move |e| e.sites.get(index).is_some_and(|s| s.name == e.original)),
+                    )
+                    .on_press(move |ex| ex.emit(ViziaEvent::EditorPicked(index)));
+                },
+            );
+        })
+        .class("editorList");
+        editor_field(cx, "Name: ", EditorField::Name, SiteEditor::name, |_| true);
+        editor_field(
             cx,
-            AppData::current_time.map(|t| format!("Last Update: {}", t.format("%r"))),
-        )
-        .class("timeStamp");
+            "Address: ",
+            EditorField::Address,
+            SiteEditor::address,
+            |a| a.is_empty() || a.parse::<IpAddr>().is_ok(),
+        );
+        editor_field(
+            cx,
+            "IPv6 address: ",
+            EditorField::AddressV6,
+            SiteEditor::address_v6,
+            |a| a.is_empty() || a.parse::<IpAddr>().is_ok_and(|a| a.is_ipv6()),
+        );
+        editor_field(cx, "Group: ", EditorField::Group, SiteEditor::group, |_| {
+            true
+        });
+        editor_field(
+            cx,
+            "Parent: ",
+            EditorField::Parent,
+            SiteEditor::parent,
+            |_| true,
+        );
+        editor_field(
+            cx,
+            "Interval: ",
+            EditorField::Interval,
+            SiteEditor::interval,
+            |i| i.is_empty() || i.parse::<u64>().is_ok_and(|i| i > 0),
+        );
+        HStack::new(cx, |cx| {
+            Element::new(cx); // Exists to take up space.
+            Label::new(cx, "Hop statistics: ").class("menuToggleLabel");
+            Switch::new(cx, AppData::editor.then(SiteEditor::mtr))
+                .on_toggle(|cx| cx.emit(ViziaEvent::EditorMtrToggled))
+                .class("menuInput");
+        })
+        .class("menuButtonBar");
+        HStack::new(cx, |cx| {
+            Element::new(cx); // Exists to take up space.
+            Label::new(cx, "Disabled: ").class("menuToggleLabel");
+            Switch::new(cx, AppData::editor.then(SiteEditor::disabled))
+                .on_toggle(|cx| cx.emit(ViziaEvent::EditorDisabledToggled))
+                .class("menuInput");
+        })
+        .class("menuButtonBar");
+        HStack::new(cx, |cx| {
+            Button::new(cx, |cx| Label::new(cx, "New"))
+                .on_press(|ex| ex.emit(ViziaEvent::EditorNewPressed))
+                .class("exportButton");
+            Button::new(cx, |cx| Label::new(cx, "Save"))
+                .on_press(|ex| ex.emit(ViziaEvent::EditorSavePressed))
+                .class("exportButton");
+            Button::new(cx, |cx| Label::new(cx, "Delete"))
+                .on_press(|ex| ex.emit(ViziaEvent::EditorDeletePressed))
+                .class("exportButton");
+        })
+        .col_between(Pixels(10.0))
+        .class("menuInputRow");
+        Label::new(cx, AppData::editor.then(SiteEditor::status))
+            .class("exportLabel")
+            .text_wrap(true);
     })
-    .class("leftPane")
+    .row_between(Pixels(10.0));
 }
 
-// Right side, timer countdown and controls.
+// One text field of the site editor.  Outlined in red while `valid` says what's typed won't save.
+fn editor_field(
+    cx: &mut Context,
+    label: &'static str,
+    field: EditorField,
+    lens: impl Lens<Source = SiteEditor, Target = String> + Copy,
+    valid: fn(&str) -> bool,
+) {
+    HStack::new(cx, |cx| {
+        Element::new(cx); // Exists to take up space.
+        Label::new(cx, label).class("menuInputLabel");
+        Textbox::new(cx, AppData::editor.then(lens))
+            .on_edit(move |ex, text| ex.emit(ViziaEvent::EditorChanged(field, text)))
+            .toggle_class(
+                "editorInvalid",
+                AppData::editor.then(lens).map(move |t| !valid(t.trim())),
+            )
+            .class("menuInput");
+    })
+    .class("menuInputRow");
+}
+
+// Right side, controls.
 fn right_side(cx: &mut Context) -> Handle<VStack> {
     VStack::new(cx, |cx| {
         HStack::new(cx, |cx| {
@@ -129,6 +649,56 @@ fn right_side(cx: &mut Context) -> Handle<VStack> {
                         })
                         .class("menuButtonBar");
 
+                        HStack::new(cx, |cx| {
+                            // Hop statistics toggle
+                            Element::new(cx); // Exists to take up space.
+                            Label::new(cx, "Hop statistics: ").class("menuToggleLabel");
+                            Switch::new(cx, AppData::show_mtr)
+                                .on_toggle(|cx| cx.emit(ViziaEvent::MtrTogglePressed))
+                                .class("menuInput");
+                        })
+                        .class("menuButtonBar");
+
+                        HStack::new(cx, |cx| {
+                            // Event log toggle
+                            Element::new(cx); // Exists to take up space.
+                            Label::new(cx, "Event log: ").class("menuToggleLabel");
+                            Switch::new(cx, AppData::show_log)
+                                .on_toggle(|cx| cx.emit(ViziaEvent::LogTogglePressed))
+                                .class("menuInput");
+                        })
+                        .class("menuButtonBar");
+
+                        HStack::new(cx, |cx| {
+                            // Tile layout toggle
+                            Element::new(cx); // Exists to take up space.
+                            Label::new(cx, "Tile layout: ").class("menuToggleLabel");
+                            Switch::new(cx, AppData::tile_layout)
+                                .on_toggle(|cx| cx.emit(ViziaEvent::TileTogglePressed))
+                                .class("menuInput");
+                        })
+                        .class("menuButtonBar");
+
+                        HStack::new(cx, |cx| {
+                            // Problems only toggle
+                            Element::new(cx); // Exists to take up space.
+                            Label::new(cx, "Only problems: ").class("menuToggleLabel");
+                            Switch::new(cx, AppData::problems_only)
+                                .on_toggle(|cx| cx.emit(ViziaEvent::ProblemsTogglePressed))
+                                .class("menuInput");
+                        })
+                        .class("menuButtonBar");
+
+                        HStack::new(cx, |cx| {
+                            // Site name filter
+                            Element::new(cx); // Exists to take up space.
+                            Label::new(cx, "Filter: ").class("menuInputLabel");
+                            Textbox::new(cx, AppData::filter)
+                                .on_edit(|ex, text| ex.emit(ViziaEvent::FilterChanged(text)))
+                                .class("menuInput");
+                        })
+                        .class("menuInputRow");
+
                         HStack::new(cx, |cx| {
                             // Timeout controls
                             Element::new(cx); // Exists to take up space.
@@ -157,7 +727,7 @@ fn right_side(cx: &mut Context) -> Handle<VStack> {
                             // Refresh now button
                             Element::new(cx); // Exists to take up space.
                             Button::new(cx, |cx| Label::new(cx, "Refresh now"))
-                                .on_press(|ex| ex.emit(ViziaEvent::TimerReset))
+                                .on_press(|ex| ex.emit(ViziaEvent::RefreshNow))
                                 .class("menuInput");
                         })
                         .class("menuInputRow");
@@ -171,6 +741,56 @@ fn right_side(cx: &mut Context) -> Handle<VStack> {
                         })
                         .class("menuInputRow");
 
+                        VStack::new(cx, |cx| {
+                            // History export
+                            Label::new(cx, "Export history: ").class("menuToggleLabel");
+                            HStack::new(cx, |cx| {
+                                Textbox::new(cx, AppData::export_from)
+                                    .on_edit(|ex, text| {
+                                        ex.emit(ViziaEvent::ExportFromChanged(text))
+                                    })
+                                    .class("exportDate");
+                                Label::new(cx, "to").class("exportLabel");
+                                Textbox::new(cx, AppData::export_to)
+                                    .on_edit(|ex, text| ex.emit(ViziaEvent::ExportToChanged(text)))
+                                    .class("exportDate");
+                                Button::new(cx, |cx| Label::new(cx, "CSV"))
+                                    .on_press(|ex| {
+                                        ex.emit(ViziaEvent::ExportPressed(ExportFormat::Csv))
+                                    })
+                                    .class("exportButton");
+                                Button::new(cx, |cx| Label::new(cx, "JSON"))
+                                    .on_press(|ex| {
+                                        ex.emit(ViziaEvent::ExportPressed(ExportFormat::Json))
+                                    })
+                                    .class("exportButton");
+                            })
+                            .col_between(Pixels(10.0))
+                            .class("menuInputRow");
+                            Label::new(cx, AppData::export_status).class("exportLabel");
+                        })
+                        .row_between(Pixels(10.0));
+
+                        site_editor(cx);
+
+                        VStack::new(cx, |cx| {
+                            // Discovery scan
+                            Label::new(cx, "Discover hosts: ").class("menuToggleLabel");
+                            HStack::new(cx, |cx| {
+                                Textbox::new(cx, AppData::discovery.then(Discovery::range))
+                                    .on_edit(|ex, text| {
+                                        ex.emit(ViziaEvent::DiscoveryRangeChanged(text))
+                                    })
+                                    .class("discoveryRange");
+                                Button::new(cx, |cx| Label::new(cx, "Scan"))
+                                    .on_press(|ex| ex.emit(ViziaEvent::DiscoveryPressed))
+                                    .class("exportButton");
+                            })
+                            .col_between(Pixels(10.0))
+                            .class("menuInputRow");
+                        })
+                        .row_between(Pixels(10.0));
+
                         VStack::new(cx, |cx| {
                             // Payload size radio
                             Label::new(cx, "Payload size: ").class("menuToggleLabel");
@@ -196,18 +816,49 @@ fn right_side(cx: &mut Context) -> Handle<VStack> {
                             .class("menuInputRow");
                         })
                         .row_between(Pixels(20.0));
+
+                        VStack::new(cx, |cx| {
+                            // Sort order radio
+                            Label::new(cx, "Sort by: ").class("menuToggleLabel");
+                            HStack::new(cx, |cx| {
+                                for i in 0..4 {
+                                    let current_sort = index_to_sort(i);
+                                    VStack::new(cx, move |cx| {
+                                        RadioButton::new(
+                                            cx,
+                                            AppData::sort.map(move |s| *s == current_sort),
+                                        )
+                                        .on_select(move |cx| {
+                                            cx.emit(ViziaEvent::SortChanged(current_sort))
+                                        })
+                                        .id(format!("sort_{i}"))
+                                        .class("menuInput");
+                                        Label::new(cx, &current_sort.to_string())
+                                            .describing(format!("sort_{i}"))
+                                            .class("menuInputLabel");
+                                    });
+                                }
+                            })
+                            .class("menuInputRow");
+                        })
+                        .row_between(Pixels(20.0));
                     })
                     .class("menuPane");
                 }
             });
         })
         .class("menuPaneContainer");
-        HStack::new(cx, |cx| {
-            Label::new(cx, "Next refresh in:").class("timerLabel");
-            Label::new(cx, AppData::timer_count).class("timerCount");
-        })
-        .class("timerPane")
-        .col_between(Stretch(1.0));
+        worker_health(cx);
+        Label::new(
+            cx,
+            AppData::probes_in_flight.map(|n| format!("Pings in flight: {n}")),
+        )
+        .class("probeStats");
+        Label::new(
+            cx,
+            AppData::probes_skipped.map(|n| format!("Pings skipped (last still out): {n}")),
+        )
+        .class("probeStats");
     })
     .class("rightPane")
     .row_between(Stretch(1.0))
@@ -225,3 +876,57 @@ fn index_to_payload(index: usize) -> Payload {
         _ => unreachable!(),
     }
 }
+
+// Helper for sort order radio buttons
+fn index_to_sort(index: usize) -> SortMode {
+    match index {
+        0 => SortMode::Name,
+        1 => SortMode::Latency,
+        2 => SortMode::Status,
+        3 => SortMode::LastChange,
+        _ => unreachable!(),
+    }
+}
+
+// Explains how to fix it when the monitor isn't allowed to ping.
+fn ping_problem(cx: &mut Context) {
+    Binding::new(cx, AppData::ping_problem, |cx, problem| {
+        if problem.get(cx).is_empty() {
+            return;
+        }
+        Label::new(cx, AppData::ping_problem)
+            .text_wrap(true)
+            .class("pingProblem");
+    });
+}
+
+// Says why sites.json wasn't reloaded, the sites before it are still being monitored.
+fn sites_problem(cx: &mut Context) {
+    Binding::new(cx, AppData::sites_problem, |cx, problem| {
+        if problem.get(cx).is_empty() {
+            return;
+        }
+        Label::new(cx, AppData::sites_problem)
+            .text_wrap(true)
+            .class("pingProblem");
+    });
+}
+
+// Warns when the tokio thread has died or had to be restarted.
+fn worker_health(cx: &mut Context) {
+    Binding::new(cx, AppData::worker_alive, |cx, alive| {
+        if !alive.get(cx) {
+            Label::new(cx, "Monitoring stopped, restarting...").class("workerDown");
+        } else {
+            Label::new(
+                cx,
+                AppData::worker_restarts.map(|n| match n {
+                    0 => String::new(),
+                    1 => "Monitoring restarted once".to_string(),
+                    n => format!("Monitoring restarted {n} times"),
+                }),
+            )
+            .class("workerRestarted");
+        }
+    });
+}
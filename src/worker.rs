@@ -1,27 +1,182 @@
 use super::*;
 
-/// Initates the runtime loop.  Must send ContextProxy first over mpsc channel, else panic!  
+use std::time::Instant;
+use tokio::sync::broadcast;
+
+/// Longest wait between restarts when the tokio thread keeps dying.
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+
+/// How long the GUI waits on exit for the tokio thread to finish up.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Starts the tokio thread under a supervisor.  Returns the sender the GUI uses to talk to it,
+/// and the supervisor's thread to wait on at exit.  
+pub fn start_worker(
+    proxy: ContextProxy,
+) -> (mpsc::Sender<TokioEvent>, std::thread::JoinHandle<()>) {
+    let (tx, rx) = worker_channel(proxy.clone());
+    let api_tx = tx.clone(); // For the web control API.
+    let supervisor = std::thread::spawn(move || supervise_worker(proxy, rx, api_tx));
+    (tx, supervisor)
+}
+
+/// Waits for the supervisor to stop after the GUI sent Shutdown, so results & events still being
+/// written aren't cut off.  Gives up after a few seconds rather than hang on exit.  
+pub fn wait_for_worker(supervisor: std::thread::JoinHandle<()>) {
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+    while !supervisor.is_finished() {
+        if Instant::now() >= deadline {
+            warn!("Tokio thread didn't stop in time, exiting anyway");
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    if supervisor.join().is_err() {
+        error!("Worker supervisor panicked");
+    }
+}
+
+/// New channel to the tokio thread with the ContextProxy already queued up.
+fn worker_channel(proxy: ContextProxy) -> (mpsc::Sender<TokioEvent>, mpsc::Receiver<TokioEvent>) {
+    let (tx, rx) = mpsc::channel::<TokioEvent>();
+    // Can't fail, we're holding the receiver.
+    let _ = tx.send(TokioEvent::EventProxy(proxy));
+    (tx, rx)
+}
+
+/// Runs the tokio thread, restarting it with a fresh channel if it panics.  
+/// Backs off while it keeps dying soon after starting.  Stops once it exits on its own or the GUI is gone.  
+fn supervise_worker(
+    mut proxy: ContextProxy,
+    mut rx: mpsc::Receiver<TokioEvent>,
+    mut api_tx: mpsc::Sender<TokioEvent>,
+) {
+    let mut delay = Duration::from_secs(1);
+    loop {
+        let started = Instant::now();
+        match std::thread::spawn(move || tokio_main(rx, api_tx)).join() {
+            Ok(()) => {
+                info!("Tokio thread stopped");
+                return;
+            }
+            Err(_) => error!("Tokio thread panicked"),
+        }
+        if proxy.emit(ViziaEvent::WorkerStopped).is_err() {
+            return;
+        }
+
+        // Ran a good while before dying, probably not the same problem as last time.
+        if started.elapsed() > MAX_RESTART_DELAY {
+            delay = Duration::from_secs(1);
+        }
+        std::thread::sleep(delay);
+        delay = (delay * 2).min(MAX_RESTART_DELAY);
+
+        let (tx, new_rx) = worker_channel(proxy.clone());
+        rx = new_rx;
+        api_tx = tx.clone();
+        info!("Restarting the tokio thread");
+        if proxy.emit(ViziaEvent::WorkerRestarted(tx)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Initates the runtime loop.  Expects ContextProxy first over the mpsc channel.  
+/// `tx` feeds the same channel, for the web control API.  Returns on Shutdown, or once the channel closes.  
+/// The web server keeps `tx` for as long as it runs, so with it enabled only Shutdown stops the thread.  
 #[tokio::main] // Creates the runtime for us.
-pub async fn tokio_main(rx: mpsc::Receiver<TokioEvent>) {
+pub async fn tokio_main(rx: mpsc::Receiver<TokioEvent>, tx: mpsc::Sender<TokioEvent>) {
     //const DEF_TIMEOUT: u64 = 4;
     //const DEF_PAYLOAD: [u8; 256] = [0; 256];
     let mut timeout: u64 = 4;
-    let mut payload = Payload::Tiny;
-    let mut sites: BTreeMap<String, IpAddr> = read_sites();
-
-    // Create the ping clients.
-    let client_v4 = Client::new(&Config::default()).expect("Couldn't create IPv4 Client!");
-    let client_v6 = Client::new(&Config::builder().kind(ICMP::V6).build())
-        .expect("Couldn't create IPv6 Client!");
+    let mut sites: BTreeMap<String, SiteConfig> = read_sites();
+    let settings = read_settings();
+    info!(sites = sites.len(), "Loaded sites.json");
 
-    // Get the context proxy.
-    let cx = match rx.recv() {
+    // Get the context proxy.  Nothing else can be handled without it.
+    let cx = loop {
         // Sleeps thread until we get something from the channel.
-        Ok(e) => match e {
-            TokioEvent::EventProxy(cx) => cx,
-            _ => panic!("Received event other than EventProxy first!"), // User error, rewrite your code.
-        },
-        Err(_e) => panic!("Channel was closed before receiving any values!"), // Sender was dropped, something went wrong.  Should be unreachable.
+        match rx.recv() {
+            Ok(TokioEvent::EventProxy(cx)) => break cx,
+            Ok(TokioEvent::Shutdown) | Err(_) => {
+                info!("Stopped before receiving the event proxy");
+                return;
+            }
+            Ok(_) => warn!("Received event before the event proxy, ignoring it"),
+        }
+    };
+
+    // Create the ping clients.  Sites can't be pinged without them, so tell the user how to fix it.
+    let client_v4 = open_client(ICMP::V4, "IPv4", &settings.socket);
+    let client_v6 = open_client(ICMP::V6, "IPv6", &settings.socket);
+    if let Some(e) = [&client_v4, &client_v6]
+        .into_iter()
+        .find_map(|c| c.as_ref().err())
+        .filter(|e| e.kind() == std::io::ErrorKind::PermissionDenied)
+    {
+        if cx
+            .clone()
+            .emit(ViziaEvent::PingUnavailable(privilege_help(e)))
+            .is_err()
+        {
+            error!("Couldn't tell the GUI pinging isn't allowed");
+        }
+    }
+    // Same wording as the per-site clients the scheduler opens.
+    let client_v4 = client_v4.map_err(|e| ClientError::new("IPv4", &e));
+    let client_v6 = client_v6.map_err(|e| ClientError::new("IPv6", &e));
+    // Discovery scans share the sites' IPv4 client.
+    let scan_client = client_v4.clone();
+    let mut scan: Option<tokio::task::AbortHandle> = None; // The running scan, to cancel it.
+
+    // Ping results go out on a broadcast channel so the GUI and web server see the same stream.
+    let (results, _) = broadcast::channel::<PingResponse>(1024);
+    tokio::spawn(forward_results(cx.clone(), results.subscribe()));
+    tokio::spawn(record_probes(results.subscribe()));
+    let metrics = MetricsStore::default();
+    metrics.set_sites(&sites);
+    tokio::spawn(record_metrics(metrics.clone(), results.subscribe()));
+    let status = StatusStore::default();
+    status.set_sites(&sites);
+    tokio::spawn(record_status(status.clone(), results.subscribe()));
+    if let Some(addr) = settings.http_listen {
+        tokio::spawn(serve(
+            addr,
+            HttpState {
+                metrics: metrics.clone(),
+                status: status.clone(),
+                tx,
+                api_token: settings.api_token.clone(),
+            },
+        ));
+    } else {
+        // Nothing needs it, don't hold our own channel open.
+        drop(tx);
+    }
+
+    // Sites are probed on their own schedule, the loop just passes changes along.
+    let (schedule_tx, schedule_rx) = tokio::sync::mpsc::unbounded_channel();
+    let prober = Prober::new(
+        cx.clone(),
+        results.clone(),
+        settings.socket.clone(),
+        client_v4,
+        client_v6,
+        settings.max_probes,
+    );
+    tokio::spawn(run_scheduler(
+        schedule_rx,
+        prober,
+        sites.clone(),
+        timeout,
+        Payload::Tiny,
+        settings.clone(),
+    ));
+    let reschedule = |event| {
+        if schedule_tx.send(event).is_err() {
+            error!("Probe scheduler has stopped");
+        }
     };
 
     // Start the loop.
@@ -31,76 +186,320 @@ pub async fn tokio_main(rx: mpsc::Receiver<TokioEvent>) {
             Ok(e) => {
                 // Handle the event
                 match e {
-                    TokioEvent::EventProxy(_) => panic!("Received another EventProxy!"), // We should not ever receive a second proxy.
-                    TokioEvent::RefreshSites => sites = read_sites(), // Recieved a signal to update the sites.
-                    TokioEvent::PayloadChanged(p) => payload = p,
-                    TokioEvent::TimeoutChanged(t) => timeout = t,
-                    TokioEvent::TimerElapsed => {
-                        // Loop through all the sites.
-                        for (name, address) in sites.iter() {
-                            // Create a SiteAddress for passing
+                    TokioEvent::EventProxy(_) => {
+                        // Results are already flowing through the first one.
+                        warn!("Received another event proxy, ignoring it");
+                    }
+                    TokioEvent::Shutdown => {
+                        info!("Shutting down the tokio thread");
+                        break;
+                    }
+                    TokioEvent::RefreshSites => {
+                        // Recieved a signal to update the sites.
+                        sites = match load_sites() {
+                            Ok(sites) => sites,
+                            Err(e) => {
+                                error!(error = %e, "Couldn't reload sites.json, keeping the current sites");
+                                continue;
+                            }
+                        };
+                        info!(sites = sites.len(), "Reloaded sites.json");
+                        metrics.set_sites(&sites);
+                        status.set_sites(&sites);
+                        reschedule(ScheduleEvent::Sites(sites.clone()));
+                    }
+                    TokioEvent::PayloadChanged(p) => {
+                        debug!(payload = %p, "Payload set");
+                        reschedule(ScheduleEvent::Payload(p));
+                    }
+                    TokioEvent::TimeoutChanged(t) => {
+                        debug!(timeout = t, "Timeout set");
+                        timeout = t;
+                        reschedule(ScheduleEvent::Timeout(t));
+                    }
+                    TokioEvent::IntervalChanged(i) => {
+                        debug!(interval = i, "Default probe interval set");
+                        metrics.set_interval(i);
+                        reschedule(ScheduleEvent::Interval(i));
+                    }
+                    TokioEvent::Api(command) => {
+                        info!(command = ?command, "Control API command");
+                        // Let the GUI make the change, it'll send it back like its own.
+                        if cx.clone().emit(command.to_vizia_event()).is_err() {
+                            error!("Couldn't pass control API command to the GUI");
+                        }
+                    }
+                    TokioEvent::Traceroute(name, id) => match sites.get(&name) {
+                        Some(config) => {
                             let site = SiteAddress {
-                                name: name.clone(),
-                                addr: *address,
+                                name,
+                                addr: config.address,
                             };
-                            match address {
-                                // Check address type and send the appropriate client to the task
-                                IpAddr::V4(_) => {
-                                    tokio::spawn(ping(
-                                        cx.clone(),
-                                        client_v4.clone(),
-                                        site,
-                                        timeout,
-                                        payload.to_bytes(),
-                                    ));
-                                }
-                                IpAddr::V6(_) => {
-                                    tokio::spawn(ping(
-                                        cx.clone(),
-                                        client_v6.clone(),
-                                        site,
-                                        timeout,
-                                        payload.to_bytes(),
-                                    ));
-                                }
+                            tokio::spawn(traceroute(cx.clone(), site, timeout, id));
+                        }
+                        None => {
+                            let error = format!("{name} isn't being monitored");
+                            if cx
+                                .clone()
+                                .emit(ViziaEvent::TraceFinished(id, error))
+                                .is_err()
+                            {
+                                error!("Couldn't send the trace result to the GUI");
                             }
                         }
+                    },
+                    TokioEvent::ProbeNow => reschedule(ScheduleEvent::ProbeNow),
+                    TokioEvent::Discover(range) => {
+                        // One scan at a time.
+                        if let Some(scan) = scan.take() {
+                            scan.abort();
+                        }
+                        let task = tokio::spawn(discover(cx.clone(), scan_client.clone(), range));
+                        scan = Some(task.abort_handle());
                     }
+                    TokioEvent::StopDiscovery => {
+                        if let Some(scan) = scan.take() {
+                            scan.abort();
+                        }
+                    }
+                }
+            }
+            Err(_e) => {
+                // Every sender is gone, nothing more will arrive.
+                info!("Event channel closed, stopping the tokio thread");
+                break;
+            }
+        }
+    }
+}
+
+/// Opens a ping client.  Tries a raw socket first, then an unprivileged datagram socket where the OS
+/// allows them (on Linux, for groups in net.ipv4.ping_group_range).  Returns the raw socket's error if both fail.  
+pub fn open_client(kind: ICMP, family: &str, options: &SocketOptions) -> std::io::Result<Client> {
+    let raw_error = match Client::new(&client_config(kind, options, socket2::Type::RAW)) {
+        Ok(client) => return mark_client(client, kind, family, options),
+        Err(e) => e,
+    };
+    #[cfg(not(windows))]
+    match Client::new(&client_config(kind, options, socket2::Type::DGRAM)) {
+        Ok(client) => {
+            info!(family, error = %raw_error, "No raw socket, using an unprivileged ping socket");
+            return mark_client(client, kind, family, options);
+        }
+        Err(e) => warn!(family, error = %e, "Couldn't open an unprivileged ping socket either"),
+    }
+    error!(family, error = %raw_error, "Couldn't open a ping socket");
+    Err(raw_error)
+}
+
+fn client_config(kind: ICMP, options: &SocketOptions, sock_type: socket2::Type) -> Config {
+    let v6 = matches!(kind, ICMP::V6);
+    let mut config = Config::builder().kind(kind).sock_type_hint(sock_type);
+    // A source address only applies to its own family.
+    if let Some(source) = options.source.filter(|s| s.is_ipv6() == v6) {
+        config = config.bind(std::net::SocketAddr::new(source, 0));
+    }
+    if let Some(interface) = &options.interface {
+        config = config.interface(interface);
+    }
+    if let Some(ttl) = options.ttl {
+        config = config.ttl(ttl);
+    }
+    config.build()
+}
+
+/// Applies the DSCP marking, which the ping library has no option for.  
+/// Pings still go out unmarked if it can't be set, better than not at all.  
+fn mark_client(
+    client: Client,
+    kind: ICMP,
+    family: &str,
+    options: &SocketOptions,
+) -> std::io::Result<Client> {
+    let Some(dscp) = options.dscp else {
+        return Ok(client);
+    };
+    if dscp > 63 {
+        warn!(dscp, "DSCP values only go up to 63, not marking pings");
+    } else if matches!(kind, ICMP::V6) {
+        warn!(family, "DSCP marking is only supported for IPv4 pings");
+    } else if let Err(e) = set_tos(&client, (dscp as u32) << 2) {
+        warn!(family, dscp, error = %e, "Couldn't set DSCP marking");
+    }
+    Ok(client)
+}
+
+#[cfg(unix)]
+fn set_tos(client: &Client, tos: u32) -> std::io::Result<()> {
+    use std::os::fd::{AsRawFd, BorrowedFd};
+    let socket = client.get_socket();
+    // SAFETY: `socket` keeps the descriptor open until we're done with it.
+    let fd = unsafe { BorrowedFd::borrow_raw(socket.as_raw_fd()) };
+    socket2::SockRef::from(&fd).set_tos(tos)
+}
+
+#[cfg(not(unix))]
+fn set_tos(_client: &Client, _tos: u32) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "not supported on this platform",
+    ))
+}
+
+/// How to give the monitor permission to ping, for the message in the GUI.
+fn privilege_help(error: &std::io::Error) -> String {
+    let fix = if cfg!(windows) {
+        "Run it as administrator."
+    } else if cfg!(target_os = "linux") {
+        "Grant it raw sockets with 'sudo setcap cap_net_raw+ep <path to mhusd_site_monitor>', \
+         or allow unprivileged pings with 'sudo sysctl -w net.ipv4.ping_group_range=\"0 2147483647\"'."
+    } else {
+        "Run it as root."
+    };
+    format!("Not allowed to send pings ({error}).  {fix}")
+}
+
+/// Why a ping client couldn't be opened.  Kept so the affected sites' results can say why.
+#[derive(Clone, Debug)]
+pub struct ClientError {
+    pub kind: std::io::ErrorKind,
+    pub message: String,
+}
+impl ClientError {
+    pub fn new(family: &str, error: &std::io::Error) -> Self {
+        ClientError {
+            kind: error.kind(),
+            message: format!("Couldn't open an {family} ping socket: {error}"),
+        }
+    }
+}
+
+/// Result for a site whose address family has no ping socket.
+pub fn unpingable(site: SiteAddress, round: u64, error: ClientError) -> PingResponse {
+    PingResponse {
+        name: site.name,
+        response: None,
+        status: io_status(error.kind),
+        error: error.message,
+        response_v6: None,
+        status_v6: None,
+        parent_down: false,
+        stale: false,
+        round,
+    }
+}
+
+/// Passes ping results on to the GUI thread.  
+pub async fn forward_results(mut cx: ContextProxy, mut rx: broadcast::Receiver<PingResponse>) {
+    loop {
+        match rx.recv().await {
+            Ok(response) => {
+                if cx.emit(ViziaEvent::PingResponse(response)).is_err() {
+                    error!("Couldn't send ping result to the GUI");
                 }
             }
-            Err(_e) => {}
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                warn!(skipped = n, "GUI fell behind on ping results");
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
         }
     }
 }
 
-/// Ping a site.  Sends a PingResponse back to the GUI thread.  
+/// Ping a site.  `round` tags the result and doubles as the ICMP sequence number.  Failures are
+/// logged when `log` is set, scans leave it off since most addresses won't answer.  
 pub async fn ping(
-    mut cx: ContextProxy,
     client: Client,
     site: SiteAddress,
+    round: u64,
     timeout: u64,
     payload: Vec<u8>,
-) {
+    log: bool,
+) -> PingResponse {
     // Create the pinger.
     let mut pinger = client.pinger(site.addr, PingIdentifier(random())).await;
     pinger.timeout(Duration::from_secs(timeout));
 
-    // Get the result, send as event back to GUI.
-    let _ = match pinger.ping(PingSequence(random()), &payload).await {
-        Ok((IcmpPacket::V4(_packet), dur)) => cx.emit(ViziaEvent::PingResponse(PingResponse {
-            name: site.name,
-            response: Some(dur),
-            is_err: false,
-        })),
-        Ok((IcmpPacket::V6(_packet), dur)) => cx.emit(ViziaEvent::PingResponse(PingResponse {
-            name: site.name,
-            response: Some(dur),
-            is_err: false,
-        })),
-        Err(_) => cx.emit(ViziaEvent::PingResponse(PingResponse {
-            name: site.name,
-            response: None,
-            is_err: true,
-        })),
+    let (response, status, error) = match pinger.ping(PingSequence(round as u16), &payload).await {
+        Ok((packet, dur)) => match unreachable_from(&packet) {
+            None => {
+                debug!(site = %site.name, addr = %site.addr, rtt = ?dur, "Probe replied");
+                (Some(dur), PingStatus::Up, String::new())
+            }
+            Some(router) => (
+                None,
+                PingStatus::Unreachable,
+                format!("Destination unreachable, reported by {router}"),
+            ),
+        },
+        Err(e) => {
+            let (status, error) = classify_error(e, timeout);
+            (None, status, error)
+        }
     };
+    if log && status != PingStatus::Up {
+        info!(site = %site.name, addr = %site.addr, status = ?status, error = %error, "Probe failed");
+    }
+    PingResponse {
+        name: site.name,
+        response,
+        status,
+        error,
+        response_v6: None,
+        status_v6: None,
+        parent_down: false,
+        stale: false,
+        round,
+    }
+}
+
+/// ICMP type of an echo reply, for each family.
+const ECHO_REPLY_V4: u8 = 0;
+const ECHO_REPLY_V6: u8 = 129;
+
+/// Who sent the reply, if it was an error (e.g. destination unreachable) rather than an echo reply.
+fn unreachable_from(packet: &IcmpPacket) -> Option<IpAddr> {
+    match packet {
+        IcmpPacket::V4(p) => {
+            (p.get_icmp_type().0 != ECHO_REPLY_V4).then(|| IpAddr::V4(p.get_source()))
+        }
+        IcmpPacket::V6(p) => {
+            (p.get_icmp_type().0 != ECHO_REPLY_V6).then(|| IpAddr::V6(p.get_source()))
+        }
+    }
+}
+
+/// Sorts a failed ping into a status, with a description for the tooltip.
+fn classify_error(error: surge_ping::SurgeError, timeout: u64) -> (PingStatus, String) {
+    use surge_ping::SurgeError;
+
+    match error {
+        SurgeError::Timeout { .. } => (
+            PingStatus::Timeout,
+            format!("No reply within {timeout} seconds"),
+        ),
+        SurgeError::IOError(e) => match io_status(e.kind()) {
+            PingStatus::PermissionDenied => (
+                PingStatus::PermissionDenied,
+                format!("Not allowed to send pings: {e}"),
+            ),
+            PingStatus::Unreachable => (
+                PingStatus::Unreachable,
+                format!("No route to the site: {e}"),
+            ),
+            status => (status, e.to_string()),
+        },
+        e => (PingStatus::Failed, e.to_string()),
+    }
+}
+
+/// Status for an IO error, from sending a ping or opening the socket for it.
+fn io_status(kind: std::io::ErrorKind) -> PingStatus {
+    use std::io::ErrorKind;
+
+    match kind {
+        ErrorKind::PermissionDenied => PingStatus::PermissionDenied,
+        ErrorKind::NetworkUnreachable | ErrorKind::HostUnreachable => PingStatus::Unreachable,
+        _ => PingStatus::Failed,
+    }
 }